@@ -0,0 +1,102 @@
+// Compares the stack machine with the register machine on generated
+// straight-line programs: how many instructions each executes and how long
+// they take. Run with `cargo bench`.
+use rminiplc0::{lower, Instruction, Operation, RegisterMachine, VirtualMachine};
+use std::{
+    io,
+    time::{Duration, Instant},
+};
+
+const VARIABLES: usize = 16;
+const RUNS: usize = 10;
+
+// A linear congruential generator, so every run sees the same programs.
+struct Random(u64);
+
+impl Random {
+    fn below(&mut self, bound: u64) -> u64 {
+        self.0 = self.0.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        (self.0 >> 33) % bound
+    }
+}
+
+fn expression(random: &mut Random, depth: usize, code: &mut Vec<Instruction>) {
+    if depth == 0 || random.below(3) == 0 {
+        match random.below(2) {
+            0 => code.push(Instruction(Operation::LIT, random.below(100) as i32 - 50)),
+            _ => code.push(Instruction(Operation::LOD, random.below(VARIABLES as u64) as i32)),
+        }
+        return;
+    }
+    expression(random, depth - 1, code);
+    match random.below(4) {
+        0 => {
+            expression(random, depth - 1, code);
+            code.push(Instruction(Operation::ADD, 0));
+        }
+        1 => {
+            expression(random, depth - 1, code);
+            code.push(Instruction(Operation::SUB, 0));
+        }
+        2 => {
+            expression(random, depth - 1, code);
+            code.push(Instruction(Operation::MUL, 0));
+        }
+        _ => {
+            code.push(Instruction(Operation::LIT, random.below(9) as i32 + 1));
+            code.push(Instruction(Operation::DIV, 0));
+        }
+    }
+}
+
+// Declarations followed by `statements` assignments, every tenth a print.
+fn program(statements: usize, depth: usize) -> Vec<Instruction> {
+    let mut random = Random(statements as u64 * 31 + depth as u64);
+    let mut code: Vec<Instruction> = (0..VARIABLES).map(|value| Instruction(Operation::LIT, value as i32)).collect();
+    for statement in 0..statements {
+        expression(&mut random, depth, &mut code);
+        if statement % 10 == 9 {
+            code.push(Instruction(Operation::WRT, 0));
+        } else {
+            code.push(Instruction(Operation::STO, random.below(VARIABLES as u64) as i32));
+        }
+    }
+    code
+}
+
+// The fastest of a few runs, with the instructions executed by one.
+fn measure(mut run: impl FnMut() -> u64) -> (u64, Duration) {
+    let mut best = Duration::MAX;
+    let mut executed = 0;
+    for _ in 0..RUNS {
+        let start = Instant::now();
+        executed = run();
+        best = best.min(start.elapsed());
+    }
+    (executed, best)
+}
+
+fn main() {
+    println!(
+        "{:>10} {:>6} {:>14} {:>15} {:>12} {:>12}",
+        "statements", "depth", "stack instrs", "register instrs", "stack", "register"
+    );
+    for (statements, depth) in [(1000, 2), (1000, 5), (100000, 2), (100000, 5)] {
+        let code = program(statements, depth);
+        let lowered = lower(&code).unwrap();
+        let (stack_executed, stack_time) = measure(|| {
+            let mut vm = VirtualMachine::new(code.clone());
+            vm.run(&mut io::sink()).unwrap();
+            vm.get_executed()
+        });
+        let (register_executed, register_time) = measure(|| {
+            let mut machine = RegisterMachine::new(lowered.clone());
+            machine.run(&mut io::sink()).unwrap();
+            machine.get_executed()
+        });
+        println!(
+            "{:>10} {:>6} {:>14} {:>15} {:>12?} {:>12?}",
+            statements, depth, stack_executed, register_executed, stack_time, register_time
+        );
+    }
+}
@@ -1,22 +1,38 @@
 use crate::{
-    error::{CompilationError, ErrorCode},
+    cfg::ControlFlowGraph,
+    dataflow,
+    error::{CompilationError, CompilationWarning, ErrorCode, WarningCode},
+    line_table::{LineTable, Span},
     tokenizer::{Token, TokenType, tokenize},
 };
-use std::{collections::HashMap, fmt};
+use std::{collections::{HashMap, HashSet}, fmt};
 
 pub fn analyze(input: String) -> Vec<Instruction> {
     let mut analyzer = Analyzer::new(tokenize(input));
     analyzer.analyze().unwrap().to_vec()
 }
+
+pub fn analyze_with_warnings(input: String) -> (Vec<Instruction>, Vec<CompilationWarning>) {
+    let mut analyzer = Analyzer::new(tokenize(input));
+    let instructions = analyzer.analyze().unwrap().to_vec();
+    (instructions, analyzer.take_warnings())
+}
+#[derive(Clone)]
 pub struct Analyzer {
     tokens: Vec<Token>,
     offset: usize,
     instructions: Vec<Instruction>,
+    line_table: LineTable,
     current_pos: (usize, usize),
     uninitialized_vars: HashMap<String, i32>,
     vars: HashMap<String, i32>,
     consts: HashMap<String, i32>,
     next_token_index: usize,
+    declared_pos: HashMap<String, (usize, usize)>,
+    read_names: HashSet<String>,
+    read_pos: HashMap<usize, (usize, usize)>,
+    unassigned_slots: Vec<i32>,
+    warnings: Vec<CompilationWarning>,
 }
 
 impl Analyzer {
@@ -25,21 +41,76 @@ impl Analyzer {
             tokens,
             offset: 0,
             instructions: Vec::new(),
+            line_table: LineTable::new(),
             current_pos: (0, 0),
             uninitialized_vars: HashMap::new(),
             vars: HashMap::new(),
             consts: HashMap::new(),
-            next_token_index: 0
+            next_token_index: 0,
+            declared_pos: HashMap::new(),
+            read_names: HashSet::new(),
+            read_pos: HashMap::new(),
+            unassigned_slots: Vec::new(),
+            warnings: Vec::new(),
         }
     }
     pub fn analyze(&mut self) -> Result<&Vec<Instruction>, CompilationError> {
         self.analyze_program().map(move |_| &self.instructions)
     }
+
+    // Appends the tokens of one REPL input and analyzes them as declarations
+    // and statements in any order. Returns where the new instructions start.
+    pub fn analyze_input(&mut self, tokens: Vec<Token>) -> Result<usize, CompilationError> {
+        let start = self.instructions.len();
+        self.tokens.extend(tokens);
+        while self.offset < self.tokens.len() {
+            let offset = self.offset;
+            self.analyze_main()?;
+            if self.offset == offset {
+                return Err(CompilationError::new_packed(
+                    self.tokens[offset].get_start_pos(),
+                    ErrorCode::ErrInvalidInput,
+                ));
+            }
+        }
+        self.check_initialization()?;
+        Ok(start)
+    }
+
+    pub fn take_warnings(&mut self) -> Vec<CompilationWarning> {
+        std::mem::take(&mut self.warnings)
+    }
+
+    // Names of the slots in slot order; constants are declared first.
+    pub fn slot_names(&self) -> Vec<String> {
+        let mut names = vec![String::new(); self.next_token_index];
+        for table in [&self.consts, &self.vars, &self.uninitialized_vars].iter() {
+            for (name, index) in table.iter() {
+                names[*index as usize] = name.clone();
+            }
+        }
+        names
+    }
+
+    pub fn instructions(&self) -> &Vec<Instruction> {
+        &self.instructions
+    }
+
+    // Span of the token each instruction was generated from.
+    pub fn line_table(&self) -> &LineTable {
+        &self.line_table
+    }
+
+    pub fn const_count(&self) -> usize {
+        self.consts.len()
+    }
     // <程序> ::= 'begin'<主过程>'end'
     fn analyze_program(&mut self) -> Result<(), CompilationError> {
         self.require_token(TokenType::Begin, ErrorCode::ErrNoBegin)?;
         self.analyze_main()?;
         self.require_token(TokenType::End, ErrorCode::ErrNoEnd)?;
+        self.check_initialization()?;
+        self.check_unused();
         Ok(())
     }
     // <主过程> ::= <常量声明><变量声明><语句序列>
@@ -71,6 +142,7 @@ impl Analyzer {
                 }
             }.clone();
             let value_string = ident.get_value_string();
+            let decl_span = ident.get_span();
             if self.is_declared(&value_string) {
                 return Err(CompilationError::new_packed(
                     self.current_pos,
@@ -97,7 +169,7 @@ impl Analyzer {
                     ))
                 }
             }
-            self.instructions.push(Instruction(Operation::LIT, val));
+            self.emit(Instruction(Operation::LIT, val), decl_span);
         } // loop finished
     }
 
@@ -120,8 +192,10 @@ impl Analyzer {
                 None => false
             };
             if !initialized {
+                self.require_token(TokenType::Semicolon, ErrorCode::ErrNoSemicolon)?;
+                let decl_span = ident.get_span();
                 self.add_uninitialized_varaible(ident);
-                self.instructions.push(Instruction(Operation::LIT, 0));
+                self.emit(Instruction(Operation::LIT, 0), decl_span);
                 continue;
             }
             self.analyze_expression()?;
@@ -171,14 +245,16 @@ impl Analyzer {
                 Some(_) => {self.unread_token(); return Ok(());},
                 None => return Ok(())
             };
+            let op_span = self.last_token_span();
             self.analyze_item()?;
-            self.instructions.push(instr);
+            self.emit(instr, op_span);
         }
     }
 
     // <赋值语句> ::= <标识符>'='<表达式>';'
     fn analyze_assignment_statement(&mut self) -> Result<(), CompilationError> {
-        let name = self.require_token(TokenType::Identifier, ErrorCode::ErrNeedIdentifier)?.get_value_string().clone();
+        let ident = self.require_token(TokenType::Identifier, ErrorCode::ErrNeedIdentifier)?;
+        let name = ident.get_value_string();
         if !self.is_declared(&name) {
             return Err(CompilationError::new_packed(self.current_pos, ErrorCode::ErrNotDeclared));
         }
@@ -186,9 +262,18 @@ impl Analyzer {
             return Err(CompilationError::new_packed(self.current_pos, ErrorCode::ErrAssignToConstant));
         }
         self.require_token(TokenType::EqualSign, ErrorCode::ErrInvalidAssignment)?;
+        let expression_start = self.instructions.len();
         self.analyze_expression()?;
         self.require_token(TokenType::Semicolon, ErrorCode::ErrNoSemicolon)?;
-        self.instructions.push(Instruction(Operation::STO, self.get_index(&name).clone()));
+        let index = *self.get_index(&name);
+        if self.instructions[expression_start..] == [Instruction(Operation::LOD, index)] {
+            self.warnings.push(CompilationWarning::new_packed(
+                ident.get_start_pos(),
+                WarningCode::WarnSelfAssignment,
+                name.clone(),
+            ));
+        }
+        self.emit(Instruction(Operation::STO, index), ident.get_span());
         if !self.is_initialized_variable(&name) {
             self.make_initialized(name);
         }
@@ -197,11 +282,12 @@ impl Analyzer {
 
     fn analyze_output_statement(&mut self) -> Result<(), CompilationError> {
         self.next_token(); // It is said to be escape-able
+        let print_span = self.last_token_span();
         self.require_token(TokenType::LeftBracket, ErrorCode::ErrInvalidPrint)?;
         self.analyze_expression()?;
         self.require_token(TokenType::RightBracket, ErrorCode::ErrInvalidPrint)?;
         self.require_token(TokenType::Semicolon, ErrorCode::ErrNoSemicolon)?;
-        self.instructions.push(Instruction(Operation::WRT, 0));
+        self.emit(Instruction(Operation::WRT, 0), print_span);
         Ok(())
     }
 
@@ -214,8 +300,9 @@ impl Analyzer {
                 Some(_) => {self.unread_token();return Ok(())},
                 None => return Ok(())
             };
+            let op_span = self.last_token_span();
             self.analyze_factor()?;
-            self.instructions.push(instr);
+            self.emit(instr, op_span);
         }
     }
 
@@ -236,20 +323,22 @@ impl Analyzer {
                 }
             },
         };
+        let sign_span = self.last_token_span();
+        if prefix == -1 {
+            self.emit(Instruction(Operation::LIT, 0), sign_span)
+        }
         match self.next_token().cloned() {
             None => return Err(CompilationError::new_packed(self.current_pos, ErrorCode::ErrIncompleteExpression)),
             Some(Token::Str(TokenType::Identifier, name, ..)) => {
                 if !self.is_declared(&name) {
                     return Err(CompilationError::new_packed(self.current_pos, ErrorCode::ErrNotDeclared));
                 }
-                if !self.is_initialized_variable(&name) && ! self.is_constant(&name) {
-                    return Err(CompilationError::new_packed(self.current_pos, ErrorCode::ErrNotInitialized));
-                }
-                self.instructions.push(Instruction(Operation::LOD, self.get_index(&name).clone()));
+                self.read_names.insert(name.clone());
+                self.read_pos.insert(self.instructions.len(), self.current_pos);
+                self.emit(Instruction(Operation::LOD, *self.get_index(&name)), self.last_token_span());
             }
             Some(Token::Integer(TokenType::UnsignedInteger, val, ..)) => {
-                let value = val.clone();
-                self.instructions.push(Instruction(Operation::LIT, value as i32));
+                self.emit(Instruction(Operation::LIT, val as i32), self.last_token_span());
             }
             Some(Token::Str(TokenType::LeftBracket, ..)) => {
                 self.analyze_expression()?;
@@ -258,11 +347,20 @@ impl Analyzer {
             _ => return Err(CompilationError::new_packed(self.current_pos, ErrorCode::ErrIncompleteExpression))
         }
         if prefix == -1 {
-            self.instructions.push(Instruction(Operation::SUB, 0))
+            self.emit(Instruction(Operation::SUB, 0), sign_span)
         }
         Ok(())
     }
 
+    fn emit(&mut self, instr: Instruction, span: Span) {
+        self.instructions.push(instr);
+        self.line_table.push(span);
+    }
+
+    fn last_token_span(&self) -> Span {
+        self.tokens[self.offset - 1].get_span()
+    }
+
     fn require_token(&mut self, ttype :TokenType, err_code: ErrorCode) -> Result<Token, CompilationError> {
         self.next_token().cloned().filter(|t| t.get_type().eq(&ttype)).ok_or(CompilationError::new_packed(self.current_pos, err_code))
     }
@@ -290,11 +388,13 @@ impl Analyzer {
             panic!("You cannot add non-identifier into sig table.");
         }
         let value_str = token.get_value_string();
+        self.declared_pos.insert(value_str.clone(), token.get_start_pos());
         match sig_type {
             Sigtype::Const => self.consts.insert(value_str, self.next_token_index as i32),
-            Sigtype::Univar => self
-                .uninitialized_vars
-                .insert(value_str, self.next_token_index as i32),
+            Sigtype::Univar => {
+                self.unassigned_slots.push(self.next_token_index as i32);
+                self.uninitialized_vars.insert(value_str, self.next_token_index as i32)
+            }
             Sigtype::Var => self.vars.insert(value_str, self.next_token_index as i32),
         };
         self.next_token_index += 1;
@@ -312,6 +412,30 @@ impl Analyzer {
         self.add(token, Sigtype::Univar);
     }
 
+    // Reading a variable is only fine when every path to the read assigns it first.
+    fn check_initialization(&self) -> Result<(), CompilationError> {
+        let cfg = ControlFlowGraph::new(&self.instructions);
+        match dataflow::first_uninitialized_read(&self.instructions, &cfg, self.next_token_index, &self.unassigned_slots) {
+            Some(index) => Err(CompilationError::new_packed(self.read_pos[&index], ErrorCode::ErrNotInitialized)),
+            None => Ok(()),
+        }
+    }
+
+    fn check_unused(&mut self) {
+        let unused = |names: &HashMap<String, i32>, code: WarningCode| {
+            names
+                .keys()
+                .filter(|name| !self.read_names.contains(*name))
+                .map(|name| CompilationWarning::new_packed(self.declared_pos[name], code, name.clone()))
+                .collect::<Vec<_>>()
+        };
+        let mut warnings = unused(&self.consts, WarningCode::WarnUnusedConstant);
+        warnings.extend(unused(&self.vars, WarningCode::WarnUnusedVariable));
+        warnings.extend(unused(&self.uninitialized_vars, WarningCode::WarnNeverAssigned));
+        self.warnings.extend(warnings);
+        self.warnings.sort_by_key(|warning| warning.get_pos());
+    }
+
     fn make_initialized(&mut self, var_name: String) {
         let item = self
             .uninitialized_vars
@@ -355,8 +479,8 @@ enum Sigtype {
     Const,
 }
 
-#[derive(Clone)]
-pub struct Instruction(Operation, i32);
+#[derive(Clone, Debug, PartialEq)]
+pub struct Instruction(pub Operation, pub i32);
 
 impl fmt::Display for Instruction {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -368,8 +492,8 @@ impl fmt::Display for Instruction {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
-#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[allow(dead_code, clippy::upper_case_acronyms)]
 pub enum Operation {
     ILL = 0,
     LIT,
@@ -381,3 +505,47 @@ pub enum Operation {
     DIV,
     WRT,
 }
+
+impl Operation {
+    pub fn from_code(code: u8) -> Option<Operation> {
+        match code {
+            0 => Some(Operation::ILL),
+            1 => Some(Operation::LIT),
+            2 => Some(Operation::LOD),
+            3 => Some(Operation::STO),
+            4 => Some(Operation::ADD),
+            5 => Some(Operation::SUB),
+            6 => Some(Operation::MUL),
+            7 => Some(Operation::DIV),
+            8 => Some(Operation::WRT),
+            _ => None,
+        }
+    }
+
+    // LIT, LOD and STO are the ones printed with their operand.
+    pub fn has_operand(&self) -> bool {
+        matches!(self, Operation::LIT | Operation::LOD | Operation::STO)
+    }
+
+    // (values popped, values pushed)
+    pub fn stack_effect(&self) -> (usize, usize) {
+        match self {
+            Operation::ILL => (0, 0),
+            Operation::LIT | Operation::LOD => (0, 1),
+            Operation::STO | Operation::WRT => (1, 0),
+            Operation::ADD | Operation::SUB | Operation::MUL | Operation::DIV => (2, 1),
+        }
+    }
+}
+
+// For each slot, the index of the instruction pushing it: the stack entries
+// which are never popped again, in the analyzer's layout the declarations.
+pub(crate) fn declarations(instructions: &[Instruction]) -> Vec<usize> {
+    let mut pushes = Vec::new();
+    for (index, instr) in instructions.iter().enumerate() {
+        let (pops, pushed) = instr.0.stack_effect();
+        pushes.truncate(pushes.len().saturating_sub(pops));
+        pushes.extend(std::iter::repeat_n(index, pushed));
+    }
+    pushes
+}
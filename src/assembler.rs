@@ -0,0 +1,88 @@
+use crate::{
+    analyzer::{Instruction, Operation},
+    error::{AssemblyError, AssemblyErrorCode},
+};
+use std::collections::HashMap;
+
+// Parses the listing printed by `Instruction`'s Display back into instructions.
+//
+//   ; comments start with ';' or '#' and run to the end of the line
+//   start:          ; a label names the index of the next instruction
+//       LIT 1
+//       LOD start   ; a label can be used wherever a number is expected
+//       ADD
+//
+// Mnemonics are case-insensitive, line numbers in errors start from 1.
+pub fn assemble(source: &str) -> Result<Vec<Instruction>, AssemblyError> {
+    let mut labels = HashMap::new();
+    let mut pending = Vec::new();
+    for (i, raw_line) in source.lines().enumerate() {
+        let line_no = i + 1;
+        let mut line = raw_line.split([';', '#']).next().unwrap().trim();
+        if let Some(colon) = line.find(':') {
+            let label = line[..colon].trim();
+            if !is_label(label) {
+                return Err(AssemblyError::new(line_no, AssemblyErrorCode::ErrInvalidLabel));
+            }
+            if labels.insert(label.to_string(), pending.len() as i32).is_some() {
+                return Err(AssemblyError::new(line_no, AssemblyErrorCode::ErrDuplicateLabel));
+            }
+            line = line[colon + 1..].trim();
+        }
+        if line.is_empty() {
+            continue;
+        }
+
+        let mut words = line.split_whitespace();
+        let mnemonic = words.next().unwrap();
+        let op = parse_mnemonic(mnemonic)
+            .ok_or_else(|| AssemblyError::new(line_no, AssemblyErrorCode::ErrUnknownMnemonic))?;
+        let operand = words.next();
+        if words.next().is_some() {
+            return Err(AssemblyError::new(line_no, AssemblyErrorCode::ErrUnexpectedOperand));
+        }
+        match (op.has_operand(), operand) {
+            (true, None) => return Err(AssemblyError::new(line_no, AssemblyErrorCode::ErrMissingOperand)),
+            (false, Some(_)) => return Err(AssemblyError::new(line_no, AssemblyErrorCode::ErrUnexpectedOperand)),
+            _ => (),
+        }
+        pending.push((line_no, op, operand.map(String::from)));
+    }
+
+    pending
+        .into_iter()
+        .map(|(line_no, op, operand)| {
+            let value = match operand {
+                None => 0,
+                Some(operand) if is_label(&operand) => *labels
+                    .get(&operand)
+                    .ok_or_else(|| AssemblyError::new(line_no, AssemblyErrorCode::ErrUndefinedLabel))?,
+                Some(operand) => operand
+                    .parse()
+                    .map_err(|_| AssemblyError::new(line_no, AssemblyErrorCode::ErrInvalidOperand))?,
+            };
+            Ok(Instruction(op, value))
+        })
+        .collect()
+}
+
+fn parse_mnemonic(mnemonic: &str) -> Option<Operation> {
+    match mnemonic.to_ascii_uppercase().as_str() {
+        "ILL" | "ILLEGAL!!!" => Some(Operation::ILL),
+        "LIT" => Some(Operation::LIT),
+        "LOD" => Some(Operation::LOD),
+        "STO" => Some(Operation::STO),
+        "ADD" => Some(Operation::ADD),
+        "SUB" => Some(Operation::SUB),
+        "MUL" => Some(Operation::MUL),
+        "DIV" => Some(Operation::DIV),
+        "WRT" => Some(Operation::WRT),
+        _ => None,
+    }
+}
+
+// Same rule as identifiers in miniplc0: a letter followed by letters and digits.
+fn is_label(word: &str) -> bool {
+    let mut chars = word.chars();
+    chars.next().is_some_and(|ch| ch.is_ascii_alphabetic()) && chars.all(|ch| ch.is_ascii_alphanumeric())
+}
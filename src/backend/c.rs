@@ -0,0 +1,113 @@
+use super::{check, evaluate_pending, identifiers, location, variable_positions, Entry};
+use crate::{
+    analyzer::{Instruction, Operation},
+    bytecode::Bytecode,
+    error::CodegenError,
+};
+use std::{collections::HashSet, fmt::Write};
+
+// C keywords up to C23, GNU's, and the names of the included headers and of
+// the prelude which a local variable would break, the object-like macros in
+// particular. Names with a '_' never come from miniplc0 programs.
+const KEYWORDS: &[&str] = &[
+    "alignas", "alignof", "asm", "auto", "bool", "break", "case", "char", "const", "constexpr", "continue",
+    "default", "do", "double", "else", "enum", "extern", "false", "float", "for", "goto", "if", "inline", "int",
+    "long", "nullptr", "register", "restrict", "return", "short", "signed", "sizeof", "static", "struct", "switch",
+    "thread_local", "true", "typedef", "typeof", "union", "unsigned", "void", "volatile", "while", "main", "printf",
+    "fprintf", "fflush", "exit", "stdin", "stdout", "stderr", "EOF", "NULL", "BUFSIZ", "FILE", "errno",
+];
+
+// Arithmetic wraps like the VM's instead of being undefined on overflow, and
+// division by zero stops the program with the VM's message.
+const PRELUDE: &str = r#"#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+
+static inline int32_t plc0_add(int32_t a, int32_t b) { return (int32_t)((uint32_t)a + (uint32_t)b); }
+static inline int32_t plc0_sub(int32_t a, int32_t b) { return (int32_t)((uint32_t)a - (uint32_t)b); }
+static inline int32_t plc0_mul(int32_t a, int32_t b) { return (int32_t)((uint32_t)a * (uint32_t)b); }
+
+static inline int32_t plc0_div(int32_t a, int32_t b, const char *where) {
+    if (b == 0) {
+        fflush(stdout);
+        fprintf(stderr, "runtime error: division by zero at %s\n", where);
+        exit(1);
+    }
+    if (a == INT32_MIN && b == -1) {
+        return INT32_MIN;
+    }
+    return a / b;
+}
+
+"#;
+
+// Translates the program into a standalone C file. Stack positions read or
+// written by LOD/STO become `int32_t` locals named after their slots, the
+// other stack entries are folded into expressions. Divisions get statements
+// of their own so their errors happen in the VM's order.
+pub fn emit_c(bytecode: &Bytecode) -> Result<String, CodegenError> {
+    let instructions = &bytecode.instructions;
+    let names = identifiers(bytecode, check(instructions)?, KEYWORDS);
+    let variables = variable_positions(instructions);
+
+    let mut body = String::new();
+    let mut declared = HashSet::new();
+    let mut stack: Vec<Entry<String>> = Vec::new();
+    let mut temps = 0;
+    for (index, Instruction(op, operand)) in instructions.iter().enumerate() {
+        let entry = match op {
+            Operation::LIT if *operand == i32::MIN => Entry::value(String::from("INT32_MIN"), false),
+            Operation::LIT => Entry::value(operand.to_string(), false),
+            Operation::LOD => Entry::value(names[*operand as usize].clone(), true),
+            Operation::STO => {
+                let value = stack.pop().unwrap();
+                evaluate_pending(&mut stack, |expr| temporary(&mut body, &mut temps, expr));
+                writeln!(body, "    {} = {};", names[*operand as usize], value.code).unwrap();
+                continue;
+            }
+            Operation::WRT => {
+                let value = stack.pop().unwrap();
+                writeln!(body, "    printf(\"%ld\\n\", (long){});", value.code).unwrap();
+                continue;
+            }
+            _ => {
+                let rhs = stack.pop().unwrap();
+                let lhs = stack.pop().unwrap();
+                let reads_variables = lhs.reads_variables || rhs.reads_variables;
+                let function = format!("{:?}", op).to_lowercase();
+                if *op == Operation::DIV {
+                    let call = format!("plc0_div({}, {}, \"{}\")", lhs.code, rhs.code, location(bytecode, index));
+                    Entry::value(temporary(&mut body, &mut temps, call), false)
+                } else {
+                    Entry::value(format!("plc0_{}({}, {})", function, lhs.code, rhs.code), reads_variables)
+                }
+            }
+        };
+        let position = stack.len();
+        if variables.contains(&position) {
+            let name = &names[position];
+            if declared.insert(position) {
+                writeln!(body, "    int32_t {} = {};", name, entry.code).unwrap();
+            } else {
+                evaluate_pending(&mut stack, |expr| temporary(&mut body, &mut temps, expr));
+                writeln!(body, "    {} = {};", name, entry.code).unwrap();
+            }
+            stack.push(Entry::variable(name.clone()));
+        } else {
+            stack.push(entry);
+        }
+    }
+
+    let mut source = String::from(PRELUDE);
+    writeln!(source, "int main(void) {{").unwrap();
+    source.push_str(&body);
+    writeln!(source, "    return 0;\n}}").unwrap();
+    Ok(source)
+}
+
+// Evaluates an expression into a fresh temporary, returning its name.
+fn temporary(body: &mut String, temps: &mut usize, expr: String) -> String {
+    writeln!(body, "    int32_t t_{} = {};", temps, expr).unwrap();
+    *temps += 1;
+    format!("t_{}", *temps - 1)
+}
@@ -0,0 +1,122 @@
+use super::{check, evaluate_pending, identifiers, location, variable_positions, Entry};
+use crate::{
+    analyzer::{Instruction, Operation},
+    bytecode::Bytecode,
+    error::CodegenError,
+};
+use std::{collections::HashSet, fmt::Write};
+
+const KEYWORDS: &[&str] = &[
+    "await", "break", "case", "catch", "class", "const", "continue", "debugger", "default", "delete", "do", "else",
+    "enum", "export", "extends", "false", "finally", "for", "function", "if", "implements", "import", "in",
+    "instanceof", "interface", "let", "new", "null", "package", "private", "protected", "public", "return", "static",
+    "super", "switch", "this", "throw", "true", "try", "typeof", "void", "while", "with", "yield", "arguments",
+    "eval", "undefined", "NaN", "Infinity", "Math", "Error", "String", "console", "module", "require", "process",
+];
+
+// Numbers are doubles, so every result is truncated back to 32 bits: `| 0`
+// wraps sums and differences, Math.imul multiplies without losing precision
+// and the quotient is truncated towards zero, INT32_MIN / -1 wrapping back to
+// INT32_MIN like the VM's. Division by zero throws with the VM's message.
+const PRELUDE: &str = r#""use strict";
+
+function plc0_div(a, b, where) {
+  if (b === 0) {
+    throw new Error("runtime error: division by zero at " + where);
+  }
+  return Math.trunc(a / b) | 0;
+}
+
+"#;
+
+// Run directly by Node, the script prints to standard output and reports
+// errors on standard error.
+const EPILOGUE: &str = r#"
+if (typeof module !== "undefined") {
+  module.exports = plc0_run;
+  if (typeof require !== "undefined" && require.main === module) {
+    try {
+      plc0_run();
+    } catch (err) {
+      console.error(err.message);
+      process.exitCode = 1;
+    }
+  }
+}
+"#;
+
+// Translates the program into a self-contained script defining
+// `plc0_run(print)`, which runs the program and passes every printed value to
+// `print`, console.log by default, so a page can collect the output itself.
+// Stack positions read or written by LOD/STO become `let` bindings named after
+// their slots, the other stack entries are folded into expressions. Divisions
+// get statements of their own so their errors happen in the VM's order.
+pub fn emit_js(bytecode: &Bytecode) -> Result<String, CodegenError> {
+    let instructions = &bytecode.instructions;
+    let names = identifiers(bytecode, check(instructions)?, KEYWORDS);
+    let variables = variable_positions(instructions);
+
+    let mut body = String::new();
+    let mut declared = HashSet::new();
+    let mut stack: Vec<Entry<String>> = Vec::new();
+    let mut temps = 0;
+    for (index, Instruction(op, operand)) in instructions.iter().enumerate() {
+        let entry = match op {
+            Operation::LIT => Entry::value(operand.to_string(), false),
+            Operation::LOD => Entry::value(names[*operand as usize].clone(), true),
+            Operation::STO => {
+                let value = stack.pop().unwrap();
+                evaluate_pending(&mut stack, |expr| temporary(&mut body, &mut temps, expr));
+                writeln!(body, "  {} = {};", names[*operand as usize], value.code).unwrap();
+                continue;
+            }
+            Operation::WRT => {
+                let value = stack.pop().unwrap();
+                writeln!(body, "  print({});", value.code).unwrap();
+                continue;
+            }
+            Operation::ADD | Operation::SUB | Operation::MUL | Operation::DIV => {
+                let rhs = stack.pop().unwrap();
+                let lhs = stack.pop().unwrap();
+                let reads_variables = lhs.reads_variables || rhs.reads_variables;
+                match op {
+                    Operation::ADD => Entry::value(format!("({} + {} | 0)", lhs.code, rhs.code), reads_variables),
+                    Operation::SUB => Entry::value(format!("({} - {} | 0)", lhs.code, rhs.code), reads_variables),
+                    Operation::MUL => Entry::value(format!("Math.imul({}, {})", lhs.code, rhs.code), reads_variables),
+                    _ => {
+                        let call = format!("plc0_div({}, {}, \"{}\")", lhs.code, rhs.code, location(bytecode, index));
+                        Entry::value(temporary(&mut body, &mut temps, call), false)
+                    }
+                }
+            }
+            Operation::ILL => unreachable!("rejected by check"),
+        };
+        let position = stack.len();
+        if variables.contains(&position) {
+            let name = &names[position];
+            if declared.insert(position) {
+                writeln!(body, "  let {} = {};", name, entry.code).unwrap();
+            } else {
+                evaluate_pending(&mut stack, |expr| temporary(&mut body, &mut temps, expr));
+                writeln!(body, "  {} = {};", name, entry.code).unwrap();
+            }
+            stack.push(Entry::variable(name.clone()));
+        } else {
+            stack.push(entry);
+        }
+    }
+
+    let mut script = String::from(PRELUDE);
+    writeln!(script, "function plc0_run(print = (value) => console.log(value)) {{").unwrap();
+    script.push_str(&body);
+    writeln!(script, "}}").unwrap();
+    script.push_str(EPILOGUE);
+    Ok(script)
+}
+
+// Evaluates an expression into a fresh constant, returning its name.
+fn temporary(body: &mut String, temps: &mut usize, expr: String) -> String {
+    writeln!(body, "  const t_{} = {};", temps, expr).unwrap();
+    *temps += 1;
+    format!("t_{}", *temps - 1)
+}
@@ -0,0 +1,447 @@
+use super::{check, identifiers, location, variable_positions};
+use crate::{
+    analyzer::{Instruction, Operation},
+    bytecode::Bytecode,
+    error::{CodegenError, CodegenErrorCode},
+};
+use std::collections::HashMap;
+
+const MAJOR_VERSION: u16 = 52;
+const ACC_PUBLIC: u16 = 0x0001;
+const ACC_PRIVATE: u16 = 0x0002;
+const ACC_STATIC: u16 = 0x0008;
+const ACC_SUPER: u16 = 0x0020;
+// Limits of the class file format: code, locals and constant pool indices
+// are counted in 16 bits.
+const MAX_CODE_LENGTH: usize = 65535;
+const MAX_LOCALS: usize = 65535;
+const MAX_CONSTANTS: usize = 65535;
+
+// Opcodes used below.
+const ICONST_0: u8 = 0x03;
+const BIPUSH: u8 = 0x10;
+const SIPUSH: u8 = 0x11;
+const LDC: u8 = 0x12;
+const LDC_W: u8 = 0x13;
+const ILOAD: u8 = 0x15;
+const ALOAD: u8 = 0x19;
+const ISTORE: u8 = 0x36;
+const SWAP: u8 = 0x5f;
+const IADD: u8 = 0x60;
+const ISUB: u8 = 0x64;
+const IMUL: u8 = 0x68;
+const IDIV: u8 = 0x6c;
+const IFNE: u8 = 0x9a;
+const IRETURN: u8 = 0xac;
+const WIDE: u8 = 0xc4;
+const RETURN: u8 = 0xb1;
+const GETSTATIC: u8 = 0xb2;
+const INVOKEVIRTUAL: u8 = 0xb6;
+const INVOKESTATIC: u8 = 0xb8;
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum Constant {
+    Utf8(String),
+    Integer(i32),
+    Class(u16),
+    String(u16),
+    NameAndType(u16, u16),
+    Fieldref(u16, u16),
+    Methodref(u16, u16),
+}
+
+#[derive(Default)]
+struct ConstantPool {
+    entries: Vec<Constant>,
+    indices: HashMap<Constant, u16>,
+}
+
+// Bytecode of one method, tracking the operand stack depth for max_stack.
+#[derive(Default)]
+struct Code {
+    bytes: Vec<u8>,
+    depth: i32,
+    max_depth: i32,
+}
+
+// A value on the stack while translating: the local of a stack position used
+// by LOD/STO, or a value on the JVM operand stack.
+#[derive(Clone, Copy, PartialEq)]
+enum Entry {
+    Local(u16),
+    Operand,
+}
+
+// Writes a class file with a `public static void main(String[])` running the
+// program. Stack positions read or written by LOD/STO get a local each, named
+// in a LocalVariableTable when the bytecode has slot names; the other entries
+// stay on the JVM operand stack. idiv already wraps on INT32_MIN / -1 like the
+// VM, a zero divisor goes through a helper printing the VM's message instead
+// of throwing. The class is named `class_name`, which `java` expects to match
+// the file name.
+pub fn emit_jvm(bytecode: &Bytecode, class_name: &str) -> Result<Vec<u8>, CodegenError> {
+    let instructions = &bytecode.instructions;
+    let names = identifiers(bytecode, check(instructions)?, &[]);
+    let positions: Vec<usize> = variable_positions(instructions).into_iter().collect();
+    // Local 0 holds the arguments.
+    if positions.len() + 1 > MAX_LOCALS {
+        let is_first_over = |instr: &Instruction| {
+            matches!(instr.0, Operation::LOD | Operation::STO) && instr.1 as usize == positions[MAX_LOCALS - 1]
+        };
+        let index = instructions.iter().position(is_first_over);
+        return Err(CodegenError::new(index.unwrap_or(0), CodegenErrorCode::ErrTooLarge));
+    }
+    let locals: HashMap<usize, u16> = positions
+        .iter()
+        .enumerate()
+        .map(|(local, position)| (*position, local as u16 + 1))
+        .collect();
+
+    let mut pool = ConstantPool::default();
+    let this_class = pool.class(class_name);
+    let object_class = pool.class("java/lang/Object");
+    let out = pool.field("java/lang/System", "out", "Ljava/io/PrintStream;");
+    let println = pool.method("java/io/PrintStream", "println", "(I)V");
+    let div = pool.method(class_name, "div", "(IILjava/lang/String;)I");
+
+    let mut code = Code::default();
+    let mut stack: Vec<Entry> = Vec::new();
+    let mut first_store: HashMap<u16, usize> = HashMap::new();
+    let has_division = instructions.iter().any(|instr| instr.0 == Operation::DIV);
+    for (index, Instruction(op, operand)) in instructions.iter().enumerate() {
+        match op {
+            Operation::LIT => code.push_int(*operand, &mut pool),
+            Operation::LOD => code.load(locals[&(*operand as usize)]),
+            Operation::STO => {
+                if let Entry::Local(local) = stack.pop().unwrap() {
+                    code.load(local);
+                }
+                let local = locals[&(*operand as usize)];
+                code.store(local);
+                first_store.entry(local).or_insert(code.bytes.len());
+                continue;
+            }
+            Operation::WRT => {
+                code.op(GETSTATIC, 1);
+                code.u16(out);
+                match stack.pop().unwrap() {
+                    Entry::Local(local) => code.load(local),
+                    Entry::Operand => code.op(SWAP, 0),
+                }
+                code.op(INVOKEVIRTUAL, -2);
+                code.u16(println);
+                continue;
+            }
+            Operation::ADD | Operation::SUB | Operation::MUL | Operation::DIV => {
+                let rhs = stack.pop().unwrap();
+                let lhs = stack.pop().unwrap();
+                match (lhs, rhs) {
+                    (Entry::Local(lhs), Entry::Operand) => {
+                        code.load(lhs);
+                        code.op(SWAP, 0);
+                    }
+                    (Entry::Local(lhs), Entry::Local(rhs)) => {
+                        code.load(lhs);
+                        code.load(rhs);
+                    }
+                    (Entry::Operand, Entry::Local(rhs)) => code.load(rhs),
+                    (Entry::Operand, Entry::Operand) => (),
+                }
+                match op {
+                    Operation::ADD => code.op(IADD, -1),
+                    Operation::SUB => code.op(ISUB, -1),
+                    Operation::MUL => code.op(IMUL, -1),
+                    _ => {
+                        let at = pool.string(&location(bytecode, index));
+                        code.ldc(at);
+                        code.op(INVOKESTATIC, -2);
+                        code.u16(div);
+                    }
+                }
+            }
+            Operation::ILL => unreachable!("rejected by check"),
+        }
+        let position = stack.len();
+        match locals.get(&position) {
+            Some(&local) => {
+                code.store(local);
+                first_store.entry(local).or_insert(code.bytes.len());
+                stack.push(Entry::Local(local));
+            }
+            None => stack.push(Entry::Operand),
+        }
+        if code.bytes.len() >= MAX_CODE_LENGTH {
+            return Err(CodegenError::new(index, CodegenErrorCode::ErrTooLarge));
+        }
+    }
+    code.op(RETURN, 0);
+
+    let mut methods = vec![];
+    let mut main_attributes = vec![];
+    if bytecode.slot_names.is_some() && !positions.is_empty() {
+        let mut table = Vec::new();
+        put_u16(&mut table, positions.len() as u16);
+        for position in &positions {
+            let local = locals[position];
+            let start = first_store.get(&local).copied().unwrap_or(code.bytes.len());
+            put_u16(&mut table, start as u16);
+            put_u16(&mut table, (code.bytes.len() - start) as u16);
+            put_u16(&mut table, pool.utf8(&names[*position]));
+            put_u16(&mut table, pool.utf8("I"));
+            put_u16(&mut table, local);
+        }
+        main_attributes.push((pool.utf8("LocalVariableTable"), table));
+    }
+    let max_locals = 1 + positions.len() as u16;
+    methods.push(method(
+        &mut pool,
+        ACC_PUBLIC | ACC_STATIC,
+        "main",
+        "([Ljava/lang/String;)V",
+        &code,
+        max_locals,
+        main_attributes,
+    ));
+    if has_division {
+        methods.push(division_method(&mut pool));
+    }
+
+    if pool.entries.len() >= MAX_CONSTANTS {
+        return Err(CodegenError::new(instructions.len(), CodegenErrorCode::ErrTooLarge));
+    }
+    let mut class = vec![0xca, 0xfe, 0xba, 0xbe];
+    put_u16(&mut class, 0);
+    put_u16(&mut class, MAJOR_VERSION);
+    pool.write(&mut class);
+    put_u16(&mut class, ACC_PUBLIC | ACC_SUPER);
+    put_u16(&mut class, this_class);
+    put_u16(&mut class, object_class);
+    put_u16(&mut class, 0); // interfaces
+    put_u16(&mut class, 0); // fields
+    put_u16(&mut class, methods.len() as u16);
+    for method in methods {
+        class.extend(method);
+    }
+    put_u16(&mut class, 0); // attributes
+    Ok(class)
+}
+
+// static int div(int a, int b, String at): prints the VM's message and exits
+// when b is zero, divides otherwise.
+fn division_method(pool: &mut ConstantPool) -> Vec<u8> {
+    let err = pool.field("java/lang/System", "err", "Ljava/io/PrintStream;");
+    let message = pool.string("runtime error: division by zero at ");
+    let concat = pool.method("java/lang/String", "concat", "(Ljava/lang/String;)Ljava/lang/String;");
+    let println = pool.method("java/io/PrintStream", "println", "(Ljava/lang/String;)V");
+    let exit = pool.method("java/lang/System", "exit", "(I)V");
+
+    let mut code = Code::default();
+    code.load(1);
+    let branch = code.bytes.len();
+    code.op(IFNE, -1);
+    code.u16(0);
+    code.op(GETSTATIC, 1);
+    code.u16(err);
+    code.ldc(message);
+    code.op(ALOAD, 1);
+    code.bytes.push(2);
+    code.op(INVOKEVIRTUAL, -1);
+    code.u16(concat);
+    code.op(INVOKEVIRTUAL, -2);
+    code.u16(println);
+    code.op(ICONST_0 + 1, 1);
+    code.op(INVOKESTATIC, -1);
+    code.u16(exit);
+    let target = code.bytes.len();
+    let offset = (target - branch) as u16;
+    code.bytes[branch + 1..branch + 3].copy_from_slice(&offset.to_be_bytes());
+    code.load(0);
+    code.load(1);
+    code.op(IDIV, -1);
+    code.op(IRETURN, -1);
+
+    // The branch target has the method's own locals and an empty stack.
+    let mut frames = Vec::new();
+    put_u16(&mut frames, 1);
+    if target < 64 {
+        frames.push(target as u8);
+    } else {
+        frames.push(251);
+        put_u16(&mut frames, target as u16);
+    }
+    let attributes = vec![(pool.utf8("StackMapTable"), frames)];
+    method(pool, ACC_PRIVATE | ACC_STATIC, "div", "(IILjava/lang/String;)I", &code, 3, attributes)
+}
+
+fn method(
+    pool: &mut ConstantPool,
+    access: u16,
+    name: &str,
+    descriptor: &str,
+    code: &Code,
+    max_locals: u16,
+    attributes: Vec<(u16, Vec<u8>)>,
+) -> Vec<u8> {
+    let mut body = Vec::new();
+    put_u16(&mut body, code.max_depth as u16);
+    put_u16(&mut body, max_locals);
+    body.extend(&(code.bytes.len() as u32).to_be_bytes());
+    body.extend(&code.bytes);
+    put_u16(&mut body, 0); // exception table
+    put_u16(&mut body, attributes.len() as u16);
+    for (name, attribute) in attributes {
+        put_u16(&mut body, name);
+        body.extend(&(attribute.len() as u32).to_be_bytes());
+        body.extend(attribute);
+    }
+
+    let mut method = Vec::new();
+    put_u16(&mut method, access);
+    put_u16(&mut method, pool.utf8(name));
+    put_u16(&mut method, pool.utf8(descriptor));
+    put_u16(&mut method, 1);
+    put_u16(&mut method, pool.utf8("Code"));
+    method.extend(&(body.len() as u32).to_be_bytes());
+    method.extend(body);
+    method
+}
+
+fn put_u16(bytes: &mut Vec<u8>, value: u16) {
+    bytes.extend(&value.to_be_bytes());
+}
+
+impl Code {
+    fn op(&mut self, opcode: u8, effect: i32) {
+        self.bytes.push(opcode);
+        self.depth += effect;
+        self.max_depth = self.max_depth.max(self.depth);
+    }
+
+    fn u16(&mut self, value: u16) {
+        put_u16(&mut self.bytes, value);
+    }
+
+    fn load(&mut self, local: u16) {
+        self.local(ILOAD, 1, local);
+    }
+
+    fn store(&mut self, local: u16) {
+        self.local(ISTORE, -1, local);
+    }
+
+    // Locals above 255 need the wide form of the instruction.
+    fn local(&mut self, opcode: u8, effect: i32, local: u16) {
+        if local < 256 {
+            self.op(opcode, effect);
+            self.bytes.push(local as u8);
+        } else {
+            self.bytes.push(WIDE);
+            self.op(opcode, effect);
+            self.u16(local);
+        }
+    }
+
+    fn ldc(&mut self, index: u16) {
+        if index < 256 {
+            self.op(LDC, 1);
+            self.bytes.push(index as u8);
+        } else {
+            self.op(LDC_W, 1);
+            self.u16(index);
+        }
+    }
+
+    fn push_int(&mut self, value: i32, pool: &mut ConstantPool) {
+        match value {
+            -1..=5 => self.op((ICONST_0 as i32 + value) as u8, 1),
+            -128..=127 => {
+                self.op(BIPUSH, 1);
+                self.bytes.push(value as i8 as u8);
+            }
+            -32768..=32767 => {
+                self.op(SIPUSH, 1);
+                self.u16(value as i16 as u16);
+            }
+            _ => {
+                let index = pool.add(Constant::Integer(value));
+                self.ldc(index);
+            }
+        }
+    }
+}
+
+impl ConstantPool {
+    fn add(&mut self, constant: Constant) -> u16 {
+        if let Some(index) = self.indices.get(&constant) {
+            return *index;
+        }
+        self.entries.push(constant.clone());
+        let index = self.entries.len() as u16;
+        self.indices.insert(constant, index);
+        index
+    }
+
+    fn utf8(&mut self, text: &str) -> u16 {
+        self.add(Constant::Utf8(text.to_string()))
+    }
+
+    fn class(&mut self, name: &str) -> u16 {
+        let name = self.utf8(name);
+        self.add(Constant::Class(name))
+    }
+
+    fn string(&mut self, text: &str) -> u16 {
+        let text = self.utf8(text);
+        self.add(Constant::String(text))
+    }
+
+    fn name_and_type(&mut self, name: &str, descriptor: &str) -> u16 {
+        let (name, descriptor) = (self.utf8(name), self.utf8(descriptor));
+        self.add(Constant::NameAndType(name, descriptor))
+    }
+
+    fn field(&mut self, class: &str, name: &str, descriptor: &str) -> u16 {
+        let (class, name_and_type) = (self.class(class), self.name_and_type(name, descriptor));
+        self.add(Constant::Fieldref(class, name_and_type))
+    }
+
+    fn method(&mut self, class: &str, name: &str, descriptor: &str) -> u16 {
+        let (class, name_and_type) = (self.class(class), self.name_and_type(name, descriptor));
+        self.add(Constant::Methodref(class, name_and_type))
+    }
+
+    fn write(&self, bytes: &mut Vec<u8>) {
+        put_u16(bytes, self.entries.len() as u16 + 1);
+        for constant in &self.entries {
+            match constant {
+                Constant::Utf8(text) => {
+                    bytes.push(1);
+                    put_u16(bytes, text.len() as u16);
+                    bytes.extend(text.as_bytes());
+                }
+                Constant::Integer(value) => {
+                    bytes.push(3);
+                    bytes.extend(&value.to_be_bytes());
+                }
+                Constant::Class(name) => {
+                    bytes.push(7);
+                    put_u16(bytes, *name);
+                }
+                Constant::String(text) => {
+                    bytes.push(8);
+                    put_u16(bytes, *text);
+                }
+                Constant::Fieldref(class, name_and_type) | Constant::Methodref(class, name_and_type) => {
+                    bytes.push(if matches!(constant, Constant::Fieldref(..)) { 9 } else { 10 });
+                    put_u16(bytes, *class);
+                    put_u16(bytes, *name_and_type);
+                }
+                Constant::NameAndType(name, descriptor) => {
+                    bytes.push(12);
+                    put_u16(bytes, *name);
+                    put_u16(bytes, *descriptor);
+                }
+            }
+        }
+    }
+}
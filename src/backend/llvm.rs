@@ -0,0 +1,142 @@
+use super::{check, identifiers, location, variable_positions};
+use crate::{
+    analyzer::{Instruction, Operation},
+    bytecode::Bytecode,
+    error::CodegenError,
+};
+use std::fmt::Write;
+
+const DECLARATIONS: &str = r#"@fmt = private unnamed_addr constant [4 x i8] c"%d\0A\00"
+@stderr = external global i8*
+
+declare i32 @printf(i8*, ...)
+declare i32 @fprintf(i8*, i8*, ...)
+declare void @exit(i32)
+"#;
+
+// A value on the stack while translating: the alloca of a stack position used
+// by LOD/STO, or an SSA value.
+enum Entry {
+    Variable(usize),
+    Value(String),
+}
+
+// Translates the program into LLVM IR text for `llc`/`clang`, linked against
+// libc for `printf`. Stack positions read or written by LOD/STO get an alloca
+// each, the other entries become SSA values. Arithmetic uses plain
+// add/sub/mul, which wrap; sdiv is guarded against a zero divisor, which exits
+// with the VM's message, and against INT32_MIN / -1, which wraps like the VM.
+// Pointers are typed (`i8*`) for the LLVM releases that still require them.
+pub fn emit_llvm(bytecode: &Bytecode) -> Result<String, CodegenError> {
+    let instructions = &bytecode.instructions;
+    // Labels share the namespace of the allocas.
+    let names = identifiers(bytecode, check(instructions)?, &["entry"]);
+    let variables = variable_positions(instructions);
+
+    let mut globals = String::new();
+    let mut body = String::new();
+    let mut failures = String::new();
+    let mut stack: Vec<Entry> = Vec::new();
+    let mut values = 0;
+    let mut fresh = || {
+        values += 1;
+        format!("%v.{}", values - 1)
+    };
+    for &position in &variables {
+        writeln!(body, "  %{} = alloca i32", names[position]).unwrap();
+    }
+    for (index, instr) in instructions.iter().enumerate() {
+        let Instruction(op, operand) = instr;
+        writeln!(body, "  ; {}", instr).unwrap();
+        let mut operand_of = |entry: Entry, body: &mut String| match entry {
+            Entry::Value(value) => value,
+            Entry::Variable(position) => {
+                let value = fresh();
+                writeln!(body, "  {} = load i32, i32* %{}", value, names[position]).unwrap();
+                value
+            }
+        };
+        let value = match op {
+            Operation::LIT => operand.to_string(),
+            Operation::LOD => operand_of(Entry::Variable(*operand as usize), &mut body),
+            Operation::STO => {
+                let value = operand_of(stack.pop().unwrap(), &mut body);
+                writeln!(body, "  store i32 {}, i32* %{}", value, names[*operand as usize]).unwrap();
+                continue;
+            }
+            Operation::WRT => {
+                let value = operand_of(stack.pop().unwrap(), &mut body);
+                let format = "i8* getelementptr inbounds ([4 x i8], [4 x i8]* @fmt, i64 0, i64 0)";
+                writeln!(body, "  call i32 (i8*, ...) @printf({}, i32 {})", format, value).unwrap();
+                continue;
+            }
+            Operation::ADD | Operation::SUB | Operation::MUL => {
+                let rhs = operand_of(stack.pop().unwrap(), &mut body);
+                let lhs = operand_of(stack.pop().unwrap(), &mut body);
+                let result = fresh();
+                let mnemonic = format!("{:?}", op).to_lowercase();
+                writeln!(body, "  {} = {} i32 {}, {}", result, mnemonic, lhs, rhs).unwrap();
+                result
+            }
+            Operation::DIV => {
+                let rhs = operand_of(stack.pop().unwrap(), &mut body);
+                let lhs = operand_of(stack.pop().unwrap(), &mut body);
+                let message = format!("runtime error: division by zero at {}\n", location(bytecode, index));
+                writeln!(
+                    globals,
+                    "@div.message.{} = private unnamed_addr constant [{} x i8] c\"{}\\00\"",
+                    index,
+                    message.len() + 1,
+                    message.replace('\n', "\\0A")
+                )
+                .unwrap();
+                let (is_zero, is_minus_one, divisor, quotient, negated, result) =
+                    (fresh(), fresh(), fresh(), fresh(), fresh(), fresh());
+                writeln!(body, "  {} = icmp eq i32 {}, 0", is_zero, rhs).unwrap();
+                writeln!(body, "  br i1 {}, label %div.fail.{}, label %div.ok.{}", is_zero, index, index).unwrap();
+                writeln!(body, "div.ok.{}:", index).unwrap();
+                writeln!(body, "  {} = icmp eq i32 {}, -1", is_minus_one, rhs).unwrap();
+                writeln!(body, "  {} = select i1 {}, i32 1, i32 {}", divisor, is_minus_one, rhs).unwrap();
+                writeln!(body, "  {} = sdiv i32 {}, {}", quotient, lhs, divisor).unwrap();
+                writeln!(body, "  {} = sub i32 0, {}", negated, lhs).unwrap();
+                writeln!(body, "  {} = select i1 {}, i32 {}, i32 {}", result, is_minus_one, negated, quotient).unwrap();
+
+                let (stream, text) = (fresh(), fresh());
+                writeln!(failures, "div.fail.{}:", index).unwrap();
+                writeln!(failures, "  {} = load i8*, i8** @stderr", stream).unwrap();
+                writeln!(
+                    failures,
+                    "  {} = getelementptr inbounds [{} x i8], [{} x i8]* @div.message.{}, i64 0, i64 0",
+                    text,
+                    message.len() + 1,
+                    message.len() + 1,
+                    index
+                )
+                .unwrap();
+                writeln!(failures, "  call i32 (i8*, i8*, ...) @fprintf(i8* {}, i8* {})", stream, text).unwrap();
+                writeln!(failures, "  call void @exit(i32 1)\n  unreachable").unwrap();
+                result
+            }
+            Operation::ILL => unreachable!("rejected by check"),
+        };
+        let position = stack.len();
+        if variables.contains(&position) {
+            writeln!(body, "  store i32 {}, i32* %{}", value, names[position]).unwrap();
+            stack.push(Entry::Variable(position));
+        } else {
+            stack.push(Entry::Value(value));
+        }
+    }
+
+    let mut module = String::from(DECLARATIONS);
+    if !globals.is_empty() {
+        writeln!(module, "\n{}", globals.trim_end()).unwrap();
+    }
+    module.push('\n');
+    writeln!(module, "define i32 @main() {{\nentry:").unwrap();
+    module.push_str(&body);
+    writeln!(module, "  ret i32 0").unwrap();
+    module.push_str(&failures);
+    writeln!(module, "}}").unwrap();
+    Ok(module)
+}
@@ -0,0 +1,106 @@
+use crate::{
+    analyzer::{Instruction, Operation},
+    bytecode::Bytecode,
+    error::{CodegenError, CodegenErrorCode},
+};
+use std::collections::BTreeSet;
+
+mod c;
+mod js;
+mod jvm;
+mod llvm;
+mod riscv;
+mod wat;
+mod x86_64;
+
+pub use c::emit_c;
+pub use js::emit_js;
+pub use jvm::emit_jvm;
+pub use llvm::emit_llvm;
+pub use riscv::{emit_riscv, RiscvTarget};
+pub use wat::emit_wat;
+pub use x86_64::emit_x86_64;
+
+// Checks that every instruction finds the stack entries it needs, so the
+// backends can translate the stack positions into variables without checks of
+// their own. Returns the highest stack depth reached.
+pub(crate) fn check(instructions: &[Instruction]) -> Result<usize, CodegenError> {
+    let mut height = 0;
+    let mut max_height = 0;
+    for (index, instr) in instructions.iter().enumerate() {
+        if instr.0 == Operation::ILL {
+            return Err(CodegenError::new(index, CodegenErrorCode::ErrIllegalInstruction));
+        }
+        let (pops, pushes) = instr.0.stack_effect();
+        if height < pops {
+            return Err(CodegenError::new(index, CodegenErrorCode::ErrStackUnderflow));
+        }
+        let is_slot = matches!(instr.0, Operation::LOD | Operation::STO);
+        if is_slot && (instr.1 < 0 || instr.1 as usize >= height - pops) {
+            return Err(CodegenError::new(index, CodegenErrorCode::ErrInvalidSlot));
+        }
+        height = height - pops + pushes;
+        max_height = max_height.max(height);
+    }
+    Ok(max_height)
+}
+
+// The stack positions read or written by LOD/STO, which the backends keep in
+// variables; the other entries are only ever used from the top of the stack.
+pub(crate) fn variable_positions(instructions: &[Instruction]) -> BTreeSet<usize> {
+    instructions
+        .iter()
+        .filter(|instr| matches!(instr.0, Operation::LOD | Operation::STO))
+        .map(|instr| instr.1 as usize)
+        .collect()
+}
+
+// A value on the stack while translating: either the variable of a stack
+// position used by LOD/STO, or code of the target computing a value that isn't
+// needed yet.
+struct Entry<T> {
+    code: T,
+    is_variable: bool,
+    reads_variables: bool,
+}
+
+impl<T> Entry<T> {
+    fn value(code: T, reads_variables: bool) -> Entry<T> {
+        Entry { code, is_variable: false, reads_variables }
+    }
+
+    fn variable(code: T) -> Entry<T> {
+        Entry { code, is_variable: true, reads_variables: true }
+    }
+}
+
+// Code still on the stack has to read the values variables have before an
+// assignment. `spill` evaluates it into a temporary and returns the code
+// reading that temporary instead.
+fn evaluate_pending<T: Default>(stack: &mut [Entry<T>], mut spill: impl FnMut(T) -> T) {
+    for pending in stack.iter_mut().filter(|entry| !entry.is_variable && entry.reads_variables) {
+        *pending = Entry::value(spill(std::mem::take(&mut pending.code)), false);
+    }
+}
+
+// Names for the stack positions `0..count`: the slot names when the bytecode
+// has them, `s_N` otherwise. miniplc0 identifiers never contain '_', so
+// appending one is enough to stay clear of the target's keywords, and
+// generated names containing one never clash with the program's.
+fn identifiers(bytecode: &Bytecode, count: usize, keywords: &[&str]) -> Vec<String> {
+    (0..count)
+        .map(|index| match bytecode.slot_names.as_ref().and_then(|names| names.get(index)) {
+            Some(name) if keywords.contains(&name.as_str()) => format!("{}_", name),
+            Some(name) => name.clone(),
+            None => format!("s_{}", index),
+        })
+        .collect()
+}
+
+// Where instruction `index` came from, for runtime error messages.
+fn location(bytecode: &Bytecode, index: usize) -> String {
+    match bytecode.line_table.as_ref().and_then(|table| table.get(index)) {
+        Some(span) => span.to_string(),
+        None => format!("instruction {}", index),
+    }
+}
@@ -0,0 +1,186 @@
+use super::{check, identifiers, location, variable_positions};
+use crate::{
+    analyzer::{Instruction, Operation},
+    bytecode::Bytecode,
+    error::CodegenError,
+};
+use std::{collections::BTreeSet, fmt::Write};
+
+// Registers for expression temporaries; values beyond these are spilled.
+const REGISTERS: &[&str] = &["t0", "t1", "t2", "t3", "t4", "t5", "t6"];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RiscvTarget {
+    Rv32,
+    Rv64,
+}
+
+// Where a stack entry is while translating. Every stack position has a word in
+// the frame at 4*N(sp): variables always live there, temporaries only once they
+// have been spilled.
+#[derive(Clone, Copy)]
+enum Location {
+    Register(usize),
+    Frame(usize),
+}
+
+struct Emitter<'a> {
+    text: String,
+    stack: Vec<Location>,
+    free: Vec<usize>,
+    variables: &'a BTreeSet<usize>,
+}
+
+// Translates the program into RV32IM or RV64IM assembly using the ecalls of
+// RARS and SPIM: 1 prints the integer in a0, 11 the character in a0, 4 the
+// string at a0, 10 exits and 93 exits with the code in a0. On RV64 the
+// arithmetic uses the *w instructions, so it wraps at 32 bits like the VM.
+pub fn emit_riscv(bytecode: &Bytecode, target: RiscvTarget) -> Result<String, CodegenError> {
+    let instructions = &bytecode.instructions;
+    let max_height = check(instructions)?;
+    let names = identifiers(bytecode, max_height, &[]);
+    let variables = variable_positions(instructions);
+    let suffix = match target {
+        RiscvTarget::Rv32 => "",
+        RiscvTarget::Rv64 => "w",
+    };
+    let frame = (4 * max_height).div_ceil(16) * 16;
+
+    let mut emitter = Emitter {
+        text: String::new(),
+        stack: Vec::new(),
+        free: (0..REGISTERS.len()).rev().collect(),
+        variables: &variables,
+    };
+    let mut messages = String::new();
+    emitter.line("    .text\n    .globl main\nmain:");
+    if frame > 0 {
+        emitter.adjust_sp(-(frame as i64));
+    }
+    for (index, instr) in instructions.iter().enumerate() {
+        let Instruction(op, operand) = instr;
+        match op {
+            Operation::LOD | Operation::STO => emitter.line(&format!("    # {} ; {}", instr, names[*operand as usize])),
+            _ => emitter.line(&format!("    # {}", instr)),
+        }
+        match op {
+            Operation::LIT => {
+                let reg = emitter.allocate();
+                emitter.line(&format!("    li {}, {}", REGISTERS[reg], operand));
+                emitter.push(reg);
+            }
+            Operation::LOD => {
+                let reg = emitter.allocate();
+                emitter.memory("lw", reg, *operand as usize);
+                emitter.push(reg);
+            }
+            Operation::STO => {
+                let reg = emitter.pop();
+                emitter.memory("sw", reg, *operand as usize);
+                emitter.free.push(reg);
+            }
+            Operation::ADD | Operation::SUB | Operation::MUL | Operation::DIV => {
+                let rhs = emitter.pop();
+                let lhs = emitter.pop();
+                let (lhs_name, rhs_name) = (REGISTERS[lhs], REGISTERS[rhs]);
+                if *op == Operation::DIV {
+                    let message = format!("runtime error: division by zero at {}\\n", location(bytecode, index));
+                    writeln!(messages, "div_message_{}:\n    .string \"{}\"", index, message).unwrap();
+                    emitter.line(&format!("    bnez {}, div_{}", rhs_name, index));
+                    emitter.line(&format!("    la a0, div_message_{}\n    j plc0_fail", index));
+                    emitter.line(&format!("div_{}:", index));
+                }
+                let mnemonic = format!("{:?}", op).to_lowercase();
+                emitter.line(&format!("    {}{} {}, {}, {}", mnemonic, suffix, lhs_name, lhs_name, rhs_name));
+                emitter.free.push(rhs);
+                emitter.push(lhs);
+            }
+            Operation::WRT => {
+                let reg = emitter.pop();
+                emitter.line(&format!("    mv a0, {}\n    li a7, 1\n    ecall", REGISTERS[reg]));
+                emitter.line("    li a0, 10\n    li a7, 11\n    ecall");
+                emitter.free.push(reg);
+            }
+            Operation::ILL => unreachable!("rejected by check"),
+        }
+    }
+    if frame > 0 {
+        emitter.adjust_sp(frame as i64);
+    }
+    emitter.line("    li a7, 10\n    ecall");
+    if !messages.is_empty() {
+        emitter.line("\nplc0_fail:\n    li a7, 4\n    ecall\n    li a0, 1\n    li a7, 93\n    ecall");
+        emitter.line(&format!("\n    .data\n{}", messages.trim_end()));
+    }
+    Ok(emitter.text)
+}
+
+impl<'a> Emitter<'a> {
+    fn line(&mut self, line: &str) {
+        writeln!(self.text, "{}", line).unwrap();
+    }
+
+    // A free register, spilling the deepest temporary held in one when none is
+    // left: it is the one needed last.
+    fn allocate(&mut self) -> usize {
+        if let Some(reg) = self.free.pop() {
+            return reg;
+        }
+        let (position, reg) = self
+            .stack
+            .iter()
+            .enumerate()
+            .find_map(|(position, location)| match location {
+                Location::Register(reg) => Some((position, *reg)),
+                Location::Frame(_) => None,
+            })
+            .expect("every register is held by the stack");
+        self.memory("sw", reg, position);
+        self.stack[position] = Location::Frame(position);
+        reg
+    }
+
+    // Variables are written to their home as soon as they are pushed, so LOD
+    // and STO only ever deal with the frame.
+    fn push(&mut self, reg: usize) {
+        let position = self.stack.len();
+        if self.variables.contains(&position) {
+            self.memory("sw", reg, position);
+            self.free.push(reg);
+            self.stack.push(Location::Frame(position));
+        } else {
+            self.stack.push(Location::Register(reg));
+        }
+    }
+
+    fn pop(&mut self) -> usize {
+        match self.stack.pop().unwrap() {
+            Location::Register(reg) => reg,
+            Location::Frame(position) => {
+                let reg = self.allocate();
+                self.memory("lw", reg, position);
+                reg
+            }
+        }
+    }
+
+    // lw/sw of the frame word of `position`, going through a0 for offsets
+    // beyond the 12-bit immediate.
+    fn memory(&mut self, mnemonic: &str, reg: usize, position: usize) {
+        let offset = 4 * position;
+        if offset < 2048 {
+            self.line(&format!("    {} {}, {}(sp)", mnemonic, REGISTERS[reg], offset));
+        } else {
+            self.line(&format!("    li a0, {}\n    add a0, a0, sp", offset));
+            self.line(&format!("    {} {}, 0(a0)", mnemonic, REGISTERS[reg]));
+        }
+    }
+
+    fn adjust_sp(&mut self, amount: i64) {
+        if (-2048..2048).contains(&amount) {
+            self.line(&format!("    addi sp, sp, {}", amount));
+        } else {
+            self.line(&format!("    li a0, {}\n    add sp, sp, a0", amount));
+        }
+    }
+}
@@ -0,0 +1,109 @@
+use super::{check, evaluate_pending, identifiers, location, variable_positions, Entry};
+use crate::{
+    analyzer::{Instruction, Operation},
+    bytecode::Bytecode,
+    error::CodegenError,
+};
+use std::fmt::Write;
+
+// i32.div_s traps on INT32_MIN / -1 as well, where the VM wraps. A zero
+// divisor calls the host's `error` with the VM's message, trapping in case the
+// host returns.
+const DIV: &str = "  (func $div (param $a i32) (param $b i32) (param $message i32) (param $length i32) (result i32)
+    (if (i32.eqz (local.get $b))
+      (then (call $error (local.get $message) (local.get $length)) (unreachable)))
+    (if (i32.and (i32.eq (local.get $a) (i32.const -2147483648)) (i32.eq (local.get $b) (i32.const -1)))
+      (then (return (local.get $a))))
+    (i32.div_s (local.get $a) (local.get $b)))
+";
+
+// Translates the program into a WebAssembly text module exporting `main` and
+// `memory`, and importing from the host `env.print`, taking an i32, and
+// `env.error`, taking the offset and length of a UTF-8 message in `memory`.
+// Stack positions read or written by LOD/STO become locals named after their
+// slots, the other entries are folded into the code computing them. Divisions
+// are stored in locals right away, so they fail in the VM's order.
+pub fn emit_wat(bytecode: &Bytecode) -> Result<String, CodegenError> {
+    let instructions = &bytecode.instructions;
+    let names = identifiers(bytecode, check(instructions)?, &[]);
+    let variables = variable_positions(instructions);
+
+    let mut body = Vec::new();
+    let mut locals: Vec<String> = variables.iter().map(|&position| names[position].clone()).collect();
+    let mut stack: Vec<Entry<Vec<String>>> = Vec::new();
+    let mut messages = String::new();
+    for (index, Instruction(op, operand)) in instructions.iter().enumerate() {
+        let entry = match op {
+            Operation::LIT => Entry::value(vec![format!("i32.const {}", operand)], false),
+            Operation::LOD => Entry::value(vec![format!("local.get ${}", names[*operand as usize])], true),
+            Operation::STO => {
+                let value = stack.pop().unwrap();
+                evaluate_pending(&mut stack, |code| temporary(&mut body, &mut locals, code));
+                body.extend(value.code);
+                body.push(format!("local.set ${}", names[*operand as usize]));
+                continue;
+            }
+            Operation::WRT => {
+                body.extend(stack.pop().unwrap().code);
+                body.push(String::from("call $print"));
+                continue;
+            }
+            _ => {
+                let rhs = stack.pop().unwrap();
+                let lhs = stack.pop().unwrap();
+                let reads_variables = lhs.reads_variables || rhs.reads_variables;
+                let mut code = lhs.code;
+                code.extend(rhs.code);
+                if *op == Operation::DIV {
+                    let location = location(bytecode, index);
+                    let message = format!("runtime error: division by zero at {}", location);
+                    code.push(format!("i32.const {}", messages.len()));
+                    code.push(format!("i32.const {}", message.len()));
+                    code.push(format!("call $div ;; {}", location));
+                    messages.push_str(&message);
+                    Entry::value(temporary(&mut body, &mut locals, code), false)
+                } else {
+                    code.push(format!("i32.{}", format!("{:?}", op).to_lowercase()));
+                    Entry::value(code, reads_variables)
+                }
+            }
+        };
+        let position = stack.len();
+        if variables.contains(&position) {
+            evaluate_pending(&mut stack, |code| temporary(&mut body, &mut locals, code));
+            body.extend(entry.code);
+            body.push(format!("local.set ${}", names[position]));
+            stack.push(Entry::variable(vec![format!("local.get ${}", names[position])]));
+        } else {
+            stack.push(entry);
+        }
+    }
+
+    let mut module = String::from("(module\n  (import \"env\" \"print\" (func $print (param i32)))\n");
+    writeln!(module, "  (import \"env\" \"error\" (func $error (param i32 i32)))").unwrap();
+    // Memory is sized in 64 KiB pages.
+    writeln!(module, "  (memory (export \"memory\") {})", messages.len().div_ceil(0x10000)).unwrap();
+    if !messages.is_empty() {
+        writeln!(module, "  (data (i32.const 0) \"{}\")", messages).unwrap();
+    }
+    module.push_str(DIV);
+    writeln!(module, "  (func (export \"main\")").unwrap();
+    for local in &locals {
+        writeln!(module, "    (local ${} i32)", local).unwrap();
+    }
+    for line in &body {
+        writeln!(module, "    {}", line).unwrap();
+    }
+    writeln!(module, "  )\n)").unwrap();
+    Ok(module)
+}
+
+// Evaluates code into a fresh local, returning the code reading it.
+fn temporary(body: &mut Vec<String>, locals: &mut Vec<String>, code: Vec<String>) -> Vec<String> {
+    let temp = format!("t_{}", locals.len());
+    body.extend(code);
+    body.push(format!("local.set ${}", temp));
+    let read = vec![format!("local.get ${}", temp)];
+    locals.push(temp);
+    read
+}
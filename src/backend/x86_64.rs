@@ -0,0 +1,134 @@
+use super::{check, identifiers, location};
+use crate::{
+    analyzer::{Instruction, Operation},
+    bytecode::Bytecode,
+    error::CodegenError,
+};
+use std::fmt::Write;
+
+// Prints %edi and a newline with the write syscall, then fails with the
+// message at %rsi of length %rdx on standard error. Neither needs libc.
+const RUNTIME: &str = r#"
+plc0_print:
+    subq $40, %rsp
+    movslq %edi, %rax
+    movq %rax, %r8
+    leaq 32(%rsp), %rsi
+    movb $10, (%rsi)
+    testq %rax, %rax
+    jns 1f
+    negq %rax
+1:  movl $10, %ecx
+2:  xorl %edx, %edx
+    divq %rcx
+    addb $'0', %dl
+    decq %rsi
+    movb %dl, (%rsi)
+    testq %rax, %rax
+    jnz 2b
+    testq %r8, %r8
+    jns 3f
+    decq %rsi
+    movb $'-', (%rsi)
+3:  leaq 33(%rsp), %rdx
+    subq %rsi, %rdx
+    movl $1, %eax
+    movl $1, %edi
+    syscall
+    addq $40, %rsp
+    ret
+
+plc0_fail:
+    movl $1, %eax
+    movl $2, %edi
+    syscall
+    movl $60, %eax
+    movl $1, %edi
+    syscall
+"#;
+
+// Translates the program into x86-64 System V assembly for GNU as, linked
+// without libc:
+//
+//   as -o prog.o prog.s && ld -o prog prog.o
+//
+// Stack position N lives at -4*(N+1)(%rbp), so each instruction becomes a few
+// moves between the frame and %eax. Arithmetic wraps like the VM's; division
+// checks for zero and for INT32_MIN / -1, which idiv would trap on.
+pub fn emit_x86_64(bytecode: &Bytecode) -> Result<String, CodegenError> {
+    let instructions = &bytecode.instructions;
+    let max_height = check(instructions)?;
+    let names = identifiers(bytecode, max_height, &[]);
+    let slot = |position: usize| format!("-{}(%rbp)", 4 * (position + 1));
+
+    let mut text = String::new();
+    let mut messages = String::new();
+    writeln!(text, "    .text\n    .globl _start\n_start:").unwrap();
+    writeln!(text, "    movq %rsp, %rbp").unwrap();
+    writeln!(text, "    subq ${}, %rsp", (4 * max_height).div_ceil(16) * 16).unwrap();
+    let mut height = 0;
+    for (index, instr) in instructions.iter().enumerate() {
+        let Instruction(op, operand) = instr;
+        match op {
+            Operation::LOD | Operation::STO => writeln!(text, "    # {} ; {}", instr, names[*operand as usize]),
+            _ => writeln!(text, "    # {}", instr),
+        }
+        .unwrap();
+        match op {
+            Operation::LIT => writeln!(text, "    movl ${}, {}", operand, slot(height)),
+            Operation::LOD => {
+                writeln!(text, "    movl {}, %eax", slot(*operand as usize)).unwrap();
+                writeln!(text, "    movl %eax, {}", slot(height))
+            }
+            Operation::STO => {
+                writeln!(text, "    movl {}, %eax", slot(height - 1)).unwrap();
+                writeln!(text, "    movl %eax, {}", slot(*operand as usize))
+            }
+            Operation::ADD | Operation::SUB | Operation::MUL => {
+                let mnemonic = match op {
+                    Operation::ADD => "addl",
+                    Operation::SUB => "subl",
+                    _ => "imull",
+                };
+                writeln!(text, "    movl {}, %eax", slot(height - 2)).unwrap();
+                writeln!(text, "    {} {}, %eax", mnemonic, slot(height - 1)).unwrap();
+                writeln!(text, "    movl %eax, {}", slot(height - 2))
+            }
+            Operation::DIV => {
+                let message = format!("runtime error: division by zero at {}\\n", location(bytecode, index));
+                writeln!(messages, ".Ldiv_message_{}:\n    .ascii \"{}\"", index, message).unwrap();
+                writeln!(text, "    movl {}, %eax", slot(height - 2)).unwrap();
+                writeln!(text, "    movl {}, %ecx", slot(height - 1)).unwrap();
+                writeln!(text, "    testl %ecx, %ecx").unwrap();
+                writeln!(text, "    jnz .Ldiv_{}", index).unwrap();
+                writeln!(text, "    leaq .Ldiv_message_{}(%rip), %rsi", index).unwrap();
+                writeln!(text, "    movl ${}, %edx", message.len() - 1).unwrap();
+                writeln!(text, "    call plc0_fail").unwrap();
+                writeln!(text, ".Ldiv_{}:", index).unwrap();
+                writeln!(text, "    cmpl $-1, %ecx").unwrap();
+                writeln!(text, "    jne .Ldiv_signed_{}", index).unwrap();
+                writeln!(text, "    negl %eax").unwrap();
+                writeln!(text, "    jmp .Ldiv_done_{}", index).unwrap();
+                writeln!(text, ".Ldiv_signed_{}:", index).unwrap();
+                writeln!(text, "    cltd").unwrap();
+                writeln!(text, "    idivl %ecx").unwrap();
+                writeln!(text, ".Ldiv_done_{}:", index).unwrap();
+                writeln!(text, "    movl %eax, {}", slot(height - 2))
+            }
+            Operation::WRT => {
+                writeln!(text, "    movl {}, %edi", slot(height - 1)).unwrap();
+                writeln!(text, "    call plc0_print")
+            }
+            Operation::ILL => unreachable!("rejected by check"),
+        }
+        .unwrap();
+        let (pops, pushes) = op.stack_effect();
+        height = height - pops + pushes;
+    }
+    writeln!(text, "    movl $60, %eax\n    xorl %edi, %edi\n    syscall").unwrap();
+    text.push_str(RUNTIME);
+    if !messages.is_empty() {
+        writeln!(text, "\n    .section .rodata\n{}", messages.trim_end()).unwrap();
+    }
+    Ok(text)
+}
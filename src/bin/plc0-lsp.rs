@@ -0,0 +1,48 @@
+// The miniplc0 language server, speaking LSP over stdin and stdout.
+use rminiplc0::LanguageServer;
+use std::{
+    io::{self, BufRead, Write},
+    process,
+};
+
+fn main() {
+    let mut server = LanguageServer::new();
+    let stdin = io::stdin();
+    let mut input = stdin.lock();
+    let stdout = io::stdout();
+    let mut output = stdout.lock();
+    // Without `exit` the client went away, which counts as a failure.
+    while let Some(message) = read_message(&mut input) {
+        for reply in server.handle(&message) {
+            write!(output, "Content-Length: {}\r\n\r\n{}", reply.len(), reply).unwrap();
+        }
+        output.flush().unwrap();
+        if let Some(code) = server.exit_code() {
+            process::exit(code);
+        }
+    }
+    process::exit(1);
+}
+
+// The body of the next message; None at the end of the input.
+fn read_message(input: &mut impl BufRead) -> Option<String> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header).ok()? == 0 {
+            return None;
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                length = value.trim().parse().ok();
+            }
+        }
+    }
+    let mut body = vec![0; length?];
+    input.read_exact(&mut body).ok()?;
+    Some(String::from_utf8_lossy(&body).into_owned())
+}
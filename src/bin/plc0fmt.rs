@@ -0,0 +1,72 @@
+// Formats miniplc0 programs in place, or checks that they are formatted:
+//
+//   plc0fmt [--check] [FILE]...
+//
+// Without files the program is read from stdin and written to stdout. With
+// --check nothing is written; the files that would change are listed and the
+// exit status is 1. Files which can't be tokenized are reported with status 2.
+use rminiplc0::format_source;
+use std::{
+    env, fs,
+    io::{self, Read},
+    process,
+};
+
+fn main() {
+    let mut check = false;
+    let mut files = Vec::new();
+    for arg in env::args().skip(1) {
+        match arg.as_str() {
+            "--check" => check = true,
+            "-h" | "--help" => {
+                println!("Usage: plc0fmt [--check] [FILE]...");
+                return;
+            }
+            _ if arg.starts_with('-') && arg != "-" => {
+                eprintln!("error: unknown option '{}'", arg);
+                process::exit(2);
+            }
+            _ => files.push(arg),
+        }
+    }
+    if files.is_empty() {
+        files.push(String::from("-"));
+    }
+
+    let mut status = 0;
+    for file in &files {
+        let source = if file == "-" {
+            let mut source = String::new();
+            io::stdin().read_to_string(&mut source).map(|_| source)
+        } else {
+            fs::read_to_string(file)
+        };
+        let source = source.unwrap_or_else(|err| {
+            eprintln!("error: {}: {}", file, err);
+            process::exit(2);
+        });
+        let formatted = match format_source(&source) {
+            Ok(formatted) => formatted,
+            Err(err) => {
+                let (line, col) = err.get_pos();
+                eprintln!("error: {}:{}:{}: {}", file, line + 1, col + 1, err.get_err_code());
+                status = 2;
+                continue;
+            }
+        };
+        if check {
+            if formatted != source {
+                println!("{}", file);
+                status = status.max(1);
+            }
+        } else if file == "-" {
+            print!("{}", formatted);
+        } else if formatted != source {
+            fs::write(file, formatted).unwrap_or_else(|err| {
+                eprintln!("error: {}: {}", file, err);
+                process::exit(2);
+            });
+        }
+    }
+    process::exit(status);
+}
@@ -0,0 +1,227 @@
+use crate::{
+    analyzer::{Analyzer, Instruction, Operation},
+    error::{BytecodeError, BytecodeErrorCode, CompilationError, CompilationWarning},
+    line_table::{LineTable, Span},
+    tokenizer::Tokenizer,
+};
+
+// Layout, all integers little endian:
+//
+//   magic          4 bytes  "PLC0"
+//   version        u16      FORMAT_VERSION
+//   flags          u16      FLAG_DEBUG_INFO and FLAG_LINE_TABLE, one per section
+//                           following the code
+//   const count    u32      slots holding constants, they come first
+//   slot count     u32      slots pushed by the declarations
+//   instr count    u32
+//   instructions   opcode u8, followed by an i32 operand for LIT/LOD/STO
+//   debug section  slot count names, each a u16 length and UTF-8 bytes
+//   line table     instr count spans, start line, start column, end line and
+//                  end column as u32, counted from 0
+pub const MAGIC: &[u8; 4] = b"PLC0";
+pub const FORMAT_VERSION: u16 = 1;
+pub const FLAG_DEBUG_INFO: u16 = 1;
+pub const FLAG_LINE_TABLE: u16 = 2;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bytecode {
+    pub const_count: u32,
+    pub slot_count: u32,
+    pub instructions: Vec<Instruction>,
+    pub slot_names: Option<Vec<String>>,
+    pub line_table: Option<LineTable>,
+}
+
+pub fn compile(input: String) -> Result<(Bytecode, Vec<CompilationWarning>), CompilationError> {
+    let mut analyzer = Analyzer::new(Tokenizer::new(&input).get_all_tokens()?);
+    let instructions = analyzer.analyze()?.to_vec();
+    let slot_names = analyzer.slot_names();
+    let bytecode = Bytecode {
+        const_count: analyzer.const_count() as u32,
+        slot_count: slot_names.len() as u32,
+        instructions,
+        slot_names: Some(slot_names),
+        line_table: Some(analyzer.line_table().clone()),
+    };
+    Ok((bytecode, analyzer.take_warnings()))
+}
+
+pub fn is_bytecode(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+impl Bytecode {
+    // For code without symbol information, like optimized code: every slot is
+    // counted as a variable. That is every entry left on the stack at the end,
+    // and any deeper LOD/STO operand from code which pops its slots again.
+    pub fn from_instructions(instructions: Vec<Instruction>) -> Bytecode {
+        let height = instructions.iter().fold(0, |height: i64, instr| {
+            let (pops, pushes) = instr.0.stack_effect();
+            height + pushes as i64 - pops as i64
+        });
+        let used = instructions
+            .iter()
+            .filter(|instr| matches!(instr.0, Operation::LOD | Operation::STO))
+            .map(|instr| instr.1 as i64 + 1)
+            .max()
+            .unwrap_or(0);
+        Bytecode {
+            const_count: 0,
+            slot_count: height.max(used).max(0) as u32,
+            instructions,
+            slot_names: None,
+            line_table: None,
+        }
+    }
+
+    // Fails on slot names longer than their u16 length can say.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, BytecodeError> {
+        let mut bytes = MAGIC.to_vec();
+        let mut flags = 0;
+        if self.slot_names.is_some() {
+            flags |= FLAG_DEBUG_INFO;
+        }
+        if self.line_table.is_some() {
+            flags |= FLAG_LINE_TABLE;
+        }
+        bytes.extend(&FORMAT_VERSION.to_le_bytes());
+        bytes.extend(&flags.to_le_bytes());
+        bytes.extend(&self.const_count.to_le_bytes());
+        bytes.extend(&self.slot_count.to_le_bytes());
+        bytes.extend(&(self.instructions.len() as u32).to_le_bytes());
+        for instr in &self.instructions {
+            bytes.push(instr.0 as u8);
+            if instr.0.has_operand() {
+                bytes.extend(&instr.1.to_le_bytes());
+            }
+        }
+        if let Some(names) = &self.slot_names {
+            for name in names {
+                if name.len() > u16::MAX as usize {
+                    return Err(BytecodeError::new(bytes.len(), BytecodeErrorCode::ErrInvalidDebugInfo));
+                }
+                bytes.extend(&(name.len() as u16).to_le_bytes());
+                bytes.extend(name.as_bytes());
+            }
+        }
+        if let Some(line_table) = &self.line_table {
+            for span in line_table.spans() {
+                for value in &[span.start.0, span.start.1, span.end.0, span.end.1] {
+                    bytes.extend(&(*value as u32).to_le_bytes());
+                }
+            }
+        }
+        Ok(bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Bytecode, BytecodeError> {
+        let mut reader = Reader { bytes, offset: 0 };
+        if reader.take(4)? != MAGIC {
+            return Err(BytecodeError::new(0, BytecodeErrorCode::ErrBadMagic));
+        }
+        if reader.u16()? != FORMAT_VERSION {
+            return Err(reader.error_before(2, BytecodeErrorCode::ErrUnsupportedVersion));
+        }
+        let flags = reader.u16()?;
+        if flags & !(FLAG_DEBUG_INFO | FLAG_LINE_TABLE) != 0 {
+            return Err(reader.error_before(2, BytecodeErrorCode::ErrUnknownFlags));
+        }
+        let const_count = reader.u32()?;
+        let slot_count = reader.u32()?;
+        if const_count > slot_count {
+            return Err(reader.error_before(8, BytecodeErrorCode::ErrInvalidHeader));
+        }
+        let instr_count = reader.u32()?;
+
+        let mut instructions = Vec::new();
+        for _ in 0..instr_count {
+            let op = match Operation::from_code(reader.u8()?) {
+                Some(Operation::ILL) | None => {
+                    return Err(reader.error_before(1, BytecodeErrorCode::ErrInvalidOpcode))
+                }
+                Some(op) => op,
+            };
+            let operand = if op.has_operand() { reader.i32()? } else { 0 };
+            let is_slot = matches!(op, Operation::LOD | Operation::STO);
+            if is_slot && (operand < 0 || operand as u32 >= slot_count) {
+                return Err(reader.error_before(4, BytecodeErrorCode::ErrInvalidSlot));
+            }
+            instructions.push(Instruction(op, operand));
+        }
+
+        let slot_names = if flags & FLAG_DEBUG_INFO != 0 {
+            let mut names = Vec::new();
+            for _ in 0..slot_count {
+                let len = reader.u16()? as usize;
+                let name = String::from_utf8(reader.take(len)?.to_vec())
+                    .map_err(|_| reader.error_before(len, BytecodeErrorCode::ErrInvalidDebugInfo))?;
+                names.push(name);
+            }
+            Some(names)
+        } else {
+            None
+        };
+        let line_table = if flags & FLAG_LINE_TABLE != 0 {
+            let mut line_table = LineTable::new();
+            for _ in 0..instr_count {
+                let start = (reader.u32()? as usize, reader.u32()? as usize);
+                let end = (reader.u32()? as usize, reader.u32()? as usize);
+                if end < start {
+                    return Err(reader.error_before(16, BytecodeErrorCode::ErrInvalidDebugInfo));
+                }
+                line_table.push(Span { start, end });
+            }
+            Some(line_table)
+        } else {
+            None
+        };
+        if reader.offset != bytes.len() {
+            return Err(BytecodeError::new(reader.offset, BytecodeErrorCode::ErrTrailingData));
+        }
+        Ok(Bytecode {
+            const_count,
+            slot_count,
+            instructions,
+            slot_names,
+            line_table,
+        })
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], BytecodeError> {
+        if self.bytes.len() - self.offset < len {
+            return Err(BytecodeError::new(self.bytes.len(), BytecodeErrorCode::ErrTruncated));
+        }
+        let taken = &self.bytes[self.offset..self.offset + len];
+        self.offset += len;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8, BytecodeError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, BytecodeError> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, BytecodeError> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn i32(&mut self) -> Result<i32, BytecodeError> {
+        Ok(self.u32()? as i32)
+    }
+
+    fn error_before(&self, len: usize, err: BytecodeErrorCode) -> BytecodeError {
+        BytecodeError::new(self.offset - len, err)
+    }
+}
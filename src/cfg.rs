@@ -0,0 +1,91 @@
+use crate::analyzer::Instruction;
+use std::{collections::BTreeSet, fmt::Write};
+
+// Instructions [start, end) run one after another without any jump in between.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BasicBlock {
+    pub start: usize,
+    pub end: usize,
+    pub successors: Vec<usize>,
+    pub predecessors: Vec<usize>,
+}
+
+#[derive(Debug)]
+pub struct ControlFlowGraph {
+    pub blocks: Vec<BasicBlock>,
+}
+
+impl ControlFlowGraph {
+    pub fn new(instructions: &[Instruction]) -> ControlFlowGraph {
+        let mut leaders = BTreeSet::new();
+        if !instructions.is_empty() {
+            leaders.insert(0);
+        }
+        for index in 0..instructions.len() {
+            let targets = control_targets(instructions, index);
+            if targets != fall_through(instructions, index) {
+                leaders.extend(targets);
+                if index + 1 < instructions.len() {
+                    leaders.insert(index + 1);
+                }
+            }
+        }
+
+        let starts: Vec<usize> = leaders.into_iter().collect();
+        let block_index = |index: usize| starts.binary_search(&index).expect("Jump into the middle of a block.");
+        let mut blocks: Vec<BasicBlock> = starts
+            .iter()
+            .enumerate()
+            .map(|(i, &start)| BasicBlock {
+                start,
+                end: starts.get(i + 1).copied().unwrap_or(instructions.len()),
+                successors: Vec::new(),
+                predecessors: Vec::new(),
+            })
+            .collect();
+        for i in 0..blocks.len() {
+            let successors: Vec<usize> = control_targets(instructions, blocks[i].end - 1)
+                .into_iter()
+                .map(block_index)
+                .collect();
+            for &successor in &successors {
+                blocks[successor].predecessors.push(i);
+            }
+            blocks[i].successors = successors;
+        }
+        ControlFlowGraph { blocks }
+    }
+
+    // Graphviz DOT, one box per block listing its instructions with their indices.
+    pub fn to_dot(&self, instructions: &[Instruction]) -> String {
+        let mut dot = String::from("digraph cfg {\n    node [shape=box, fontname=\"monospace\"];\n");
+        for (i, block) in self.blocks.iter().enumerate() {
+            let mut label = format!("B{}\\l", i);
+            for (index, instr) in instructions.iter().enumerate().take(block.end).skip(block.start) {
+                write!(label, "{}: {}\\l", index, instr).unwrap();
+            }
+            writeln!(dot, "    B{} [label=\"{}\"];", i, label).unwrap();
+        }
+        for (i, block) in self.blocks.iter().enumerate() {
+            for successor in &block.successors {
+                writeln!(dot, "    B{} -> B{};", i, successor).unwrap();
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+// Instruction indices that may run right after `index`. The instruction set has
+// no jumps yet, so control always falls through; jumps only need to be added here.
+fn control_targets(instructions: &[Instruction], index: usize) -> Vec<usize> {
+    fall_through(instructions, index)
+}
+
+fn fall_through(instructions: &[Instruction], index: usize) -> Vec<usize> {
+    if index + 1 < instructions.len() {
+        vec![index + 1]
+    } else {
+        Vec::new()
+    }
+}
@@ -0,0 +1,70 @@
+use crate::{
+    analyzer::{Instruction, Operation},
+    cfg::ControlFlowGraph,
+};
+
+// Definite assignment: a slot is assigned at some point when every path from the
+// entry to that point stores into it. `unassigned` are the slots declared
+// without a value; every other slot is assigned by its declaration.
+// Returns the index of the first LOD that may read an unassigned slot.
+pub fn first_uninitialized_read(
+    instructions: &[Instruction],
+    cfg: &ControlFlowGraph,
+    slot_count: usize,
+    unassigned: &[i32],
+) -> Option<usize> {
+    let mut entry_state = vec![true; slot_count];
+    for &slot in unassigned {
+        entry_state[slot as usize] = false;
+    }
+
+    // None stands for a block not reached yet, which is the top of the lattice.
+    let mut block_in: Vec<Option<Vec<bool>>> = vec![None; cfg.blocks.len()];
+    let mut block_out: Vec<Option<Vec<bool>>> = vec![None; cfg.blocks.len()];
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for (i, block) in cfg.blocks.iter().enumerate() {
+            let mut state = if i == 0 { Some(entry_state.clone()) } else { None };
+            for &predecessor in &block.predecessors {
+                if let Some(out) = &block_out[predecessor] {
+                    state = Some(match state {
+                        Some(current) => current.iter().zip(out).map(|(a, b)| *a && *b).collect(),
+                        None => out.clone(),
+                    });
+                }
+            }
+            let out = state.clone().map(|mut state| {
+                transfer(&instructions[block.start..block.end], &mut state);
+                state
+            });
+            if out != block_out[i] {
+                block_out[i] = out;
+                changed = true;
+            }
+            block_in[i] = state;
+        }
+    }
+
+    for (i, block) in cfg.blocks.iter().enumerate() {
+        let mut state = match &block_in[i] {
+            Some(state) => state.clone(),
+            None => continue, // unreachable
+        };
+        for (index, instr) in instructions.iter().enumerate().take(block.end).skip(block.start) {
+            if instr.0 == Operation::LOD && !state[instr.1 as usize] {
+                return Some(index);
+            }
+            transfer(std::slice::from_ref(instr), &mut state);
+        }
+    }
+    None
+}
+
+fn transfer(instructions: &[Instruction], state: &mut [bool]) {
+    for instr in instructions {
+        if instr.0 == Operation::STO {
+            state[instr.1 as usize] = true;
+        }
+    }
+}
@@ -0,0 +1,281 @@
+use crate::{
+    analyzer::{declarations, Operation},
+    bytecode::Bytecode,
+    vm::VirtualMachine,
+};
+use std::io::Write;
+
+const HELP: &str = "\
+step, s               execute one instruction
+next, n               execute up to the next source line
+continue, c           run until a breakpoint, a watchpoint or the end
+break, b INDEX        stop before the instruction INDEX
+break, b line LINE    stop before the first instruction of source line LINE
+delete, d INDEX       remove the breakpoint at instruction INDEX
+watch, w NAME         stop whenever the variable NAME changes
+unwatch NAME          remove the watchpoint on NAME
+stack                 print the data stack
+vars                  print the named slots
+print, p NAME         print one variable or constant
+list, l               print the instructions around the current one
+quit, q               leave the debugger
+";
+
+// Drives a `VirtualMachine` one command at a time. Slots are shown by the
+// names the analyzer gave them when the bytecode carries them, and stops are
+// reported with source positions when it has a line table.
+pub struct Debugger {
+    bytecode: Bytecode,
+    vm: VirtualMachine,
+    // The instruction pushing each slot; the slot holds its value once that
+    // instruction has run.
+    declarations: Vec<usize>,
+    breakpoints: Vec<usize>,
+    watchpoints: Vec<usize>,
+    failed: bool,
+    // Where the program last stopped, so resuming there doesn't stop at its
+    // breakpoint again.
+    stopped_at: Option<usize>,
+}
+
+impl Debugger {
+    pub fn new(bytecode: Bytecode) -> Debugger {
+        let mut vm = VirtualMachine::new(bytecode.instructions.clone());
+        if let Some(line_table) = &bytecode.line_table {
+            vm.set_line_table(line_table.clone());
+        }
+        Debugger {
+            declarations: declarations(&bytecode.instructions),
+            bytecode,
+            vm,
+            breakpoints: Vec::new(),
+            watchpoints: Vec::new(),
+            failed: false,
+            stopped_at: None,
+        }
+    }
+
+    pub fn get_vm(&self) -> &VirtualMachine {
+        &self.vm
+    }
+
+    // Runs one command line; the program's own output goes to `output` as well.
+    // Returns false once the user asked to quit.
+    pub fn execute(&mut self, command: &str, output: &mut dyn Write) -> bool {
+        let words: Vec<&str> = command.split_whitespace().collect();
+        let result = match words.as_slice() {
+            [] => Ok(()),
+            ["quit"] | ["q"] => return false,
+            ["help"] | ["h"] => {
+                write!(output, "{}", HELP).unwrap();
+                Ok(())
+            }
+            ["step"] | ["s"] => self.resume(output, |_| true),
+            ["next"] | ["n"] => {
+                let line = self.current_line();
+                self.resume(output, move |debugger| line.is_none() || debugger.current_line() != line)
+            }
+            ["continue"] | ["c"] => self.resume(output, |_| false),
+            ["break", "line", line] | ["b", "line", line] => self.break_at_line(line, output),
+            ["break", index] | ["b", index] => self.parse_index(index).map(|index| self.add_breakpoint(index, output)),
+            ["delete", index] | ["d", index] => self.parse_index(index).and_then(|index| {
+                let count = self.breakpoints.len();
+                self.breakpoints.retain(|breakpoint| *breakpoint != index);
+                if self.breakpoints.len() == count {
+                    Err(format!("no breakpoint at {}", index))
+                } else {
+                    Ok(())
+                }
+            }),
+            ["watch", name] | ["w", name] => self.slot_of(name).map(|slot| {
+                if !self.watchpoints.contains(&slot) {
+                    self.watchpoints.push(slot);
+                }
+            }),
+            ["unwatch", name] => self.slot_of(name).map(|slot| self.watchpoints.retain(|watched| *watched != slot)),
+            ["stack"] => {
+                self.print_stack(output);
+                Ok(())
+            }
+            ["vars"] => self.print_vars(output),
+            ["print", name] | ["p", name] => self.slot_of(name).map(|slot| self.print_slot(slot, output)),
+            ["list"] | ["l"] => {
+                self.print_listing(output);
+                Ok(())
+            }
+            _ => Err(format!("unknown command '{}', try 'help'", command.trim())),
+        };
+        if let Err(message) = result {
+            writeln!(output, "error: {}", message).unwrap();
+        }
+        true
+    }
+
+    // Executes instructions until `stop` says so, a breakpoint or watchpoint
+    // is hit, or the program ends, then reports where it stopped. A breakpoint
+    // on the current instruction counts too, unless the program stopped there
+    // already.
+    fn resume<F>(&mut self, output: &mut dyn Write, stop: F) -> Result<(), String>
+    where
+        F: Fn(&Debugger) -> bool,
+    {
+        if self.failed || self.vm.is_finished() {
+            return Err(String::from("the program is not running"));
+        }
+        let pc = self.vm.get_pc();
+        if self.stopped_at != Some(pc) && self.breakpoints.contains(&pc) {
+            writeln!(output, "breakpoint at {}", pc).unwrap();
+            self.stopped_at = Some(pc);
+            self.print_current(output);
+            return Ok(());
+        }
+        loop {
+            let before: Vec<Option<i32>> = self.watchpoints.iter().map(|slot| self.slot_value(*slot)).collect();
+            if let Err(err) = self.vm.step(output) {
+                self.failed = true;
+                writeln!(output, "runtime error: {}", err).unwrap();
+                return Ok(());
+            }
+            let mut stopped = false;
+            for (slot, old) in self.watchpoints.iter().zip(before) {
+                let new = self.slot_value(*slot);
+                if new != old {
+                    let show = |value: Option<i32>| value.map_or(String::from("?"), |value| value.to_string());
+                    writeln!(output, "watchpoint {}: {} -> {}", self.slot_name(*slot), show(old), show(new)).unwrap();
+                    stopped = true;
+                }
+            }
+            if self.vm.is_finished() {
+                writeln!(output, "program finished").unwrap();
+                return Ok(());
+            }
+            if self.breakpoints.contains(&self.vm.get_pc()) {
+                writeln!(output, "breakpoint at {}", self.vm.get_pc()).unwrap();
+                stopped = true;
+            }
+            if stopped || stop(self) {
+                self.stopped_at = Some(self.vm.get_pc());
+                self.print_current(output);
+                return Ok(());
+            }
+        }
+    }
+
+    fn break_at_line(&mut self, line: &str, output: &mut dyn Write) -> Result<(), String> {
+        let line: usize = line.parse().map_err(|_| format!("invalid line '{}'", line))?;
+        let line_table = self.bytecode.line_table.as_ref().ok_or("the bytecode has no line table")?;
+        let index = line_table
+            .spans()
+            .iter()
+            .position(|span| span.start.0 + 1 == line)
+            .ok_or_else(|| format!("no code on line {}", line))?;
+        self.add_breakpoint(index, output);
+        Ok(())
+    }
+
+    fn add_breakpoint(&mut self, index: usize, output: &mut dyn Write) {
+        if !self.breakpoints.contains(&index) {
+            self.breakpoints.push(index);
+        }
+        writeln!(output, "breakpoint at {}{}", index, self.location(index)).unwrap();
+    }
+
+    fn parse_index(&self, index: &str) -> Result<usize, String> {
+        match index.parse() {
+            Ok(index) if index < self.bytecode.instructions.len() => Ok(index),
+            _ => Err(format!("no instruction '{}'", index)),
+        }
+    }
+
+    fn print_stack(&self, output: &mut dyn Write) {
+        let stack = self.vm.get_stack();
+        if stack.is_empty() {
+            writeln!(output, "the stack is empty").unwrap();
+        }
+        for (index, value) in stack.iter().enumerate().rev() {
+            match self.slot_names().and_then(|names| names.get(index)).filter(|_| self.is_declared(index)) {
+                Some(name) => writeln!(output, "{:>4}  {:<11} ; {}", index, value, name).unwrap(),
+                None => writeln!(output, "{:>4}  {}", index, value).unwrap(),
+            }
+        }
+    }
+
+    fn print_vars(&self, output: &mut dyn Write) -> Result<(), String> {
+        let count = self.slot_names().ok_or("the bytecode has no slot names")?.len();
+        for slot in 0..count {
+            self.print_slot(slot, output);
+        }
+        Ok(())
+    }
+
+    fn print_slot(&self, slot: usize, output: &mut dyn Write) {
+        let kind = if slot < self.bytecode.const_count as usize { "const" } else { "var" };
+        match self.slot_value(slot) {
+            Some(value) => writeln!(output, "{} {} = {}", kind, self.slot_name(slot), value),
+            None => writeln!(output, "{} {} is not declared yet", kind, self.slot_name(slot)),
+        }
+        .unwrap();
+    }
+
+    fn print_listing(&self, output: &mut dyn Write) {
+        let pc = self.vm.get_pc();
+        let first = pc.saturating_sub(3);
+        for (index, instr) in self.bytecode.instructions.iter().enumerate().skip(first).take(pc + 4 - first) {
+            let marker = if index == pc { "=>" } else { "  " };
+            writeln!(output, "{} {:>4}  {}{}", marker, index, instr, self.location(index)).unwrap();
+        }
+    }
+
+    fn print_current(&self, output: &mut dyn Write) {
+        let pc = self.vm.get_pc();
+        let instr = &self.bytecode.instructions[pc];
+        let name = match instr.0 {
+            Operation::LOD | Operation::STO => format!(" ; {}", self.slot_name(instr.1 as usize)),
+            _ => String::new(),
+        };
+        writeln!(output, "=> {:>4}  {}{}{}", pc, instr, name, self.location(pc)).unwrap();
+    }
+
+    fn location(&self, index: usize) -> String {
+        match self.bytecode.line_table.as_ref().and_then(|table| table.get(index)) {
+            Some(span) => format!(" (at {})", span),
+            None => String::new(),
+        }
+    }
+
+    fn current_line(&self) -> Option<usize> {
+        let line_table = self.bytecode.line_table.as_ref()?;
+        line_table.get(self.vm.get_pc()).map(|span| span.start.0)
+    }
+
+    fn slot_names(&self) -> Option<&Vec<String>> {
+        self.bytecode.slot_names.as_ref()
+    }
+
+    fn slot_name(&self, slot: usize) -> String {
+        match self.slot_names().and_then(|names| names.get(slot)) {
+            Some(name) => name.clone(),
+            None => format!("#{}", slot),
+        }
+    }
+
+    // Variables by name, or any slot as #INDEX.
+    fn slot_of(&self, name: &str) -> Result<usize, String> {
+        let slot = match name.strip_prefix('#') {
+            Some(index) => index.parse().ok(),
+            None => self.slot_names().and_then(|names| names.iter().position(|slot_name| slot_name == name)),
+        };
+        slot.filter(|slot| *slot < self.bytecode.slot_count as usize)
+            .ok_or_else(|| format!("no variable '{}'", name))
+    }
+
+    // Until then the stack position holds an intermediate value of the
+    // initializer, or nothing at all.
+    fn slot_value(&self, slot: usize) -> Option<i32> {
+        self.vm.get_stack().get(slot).copied().filter(|_| self.is_declared(slot))
+    }
+
+    fn is_declared(&self, slot: usize) -> bool {
+        self.declarations.get(slot).is_some_and(|&index| index < self.vm.get_pc())
+    }
+}
@@ -0,0 +1,33 @@
+use crate::{analyzer::Operation, bytecode::Bytecode};
+use std::fmt::Write;
+
+// Listing with the index of every instruction, the names of the slots used by
+// LOD/STO, and a comment line before the instructions of each source line.
+// Names and source lines are left out when the bytecode has no such info.
+pub fn disassemble(bytecode: &Bytecode, source: Option<&str>) -> String {
+    let source_lines: Vec<&str> = source.map(|source| source.lines().collect()).unwrap_or_default();
+    let width = bytecode.instructions.len().saturating_sub(1).to_string().len();
+    let mut listing = String::new();
+    let mut last_line = None;
+    for (index, instr) in bytecode.instructions.iter().enumerate() {
+        if let Some(line) = bytecode.line_table.as_ref().and_then(|table| table.get(index)).map(|span| span.start.0) {
+            if last_line != Some(line) {
+                match source_lines.get(line) {
+                    Some(text) => writeln!(listing, "; {}: {}", line + 1, text.trim()).unwrap(),
+                    None => writeln!(listing, "; line {}", line + 1).unwrap(),
+                }
+                last_line = Some(line);
+            }
+        }
+        let name = match (instr.0, &bytecode.slot_names) {
+            (Operation::LOD, Some(names)) | (Operation::STO, Some(names)) => names.get(instr.1 as usize),
+            _ => None,
+        };
+        match name {
+            Some(name) => writeln!(listing, "{:>width$}  {:<10} ; {}", index, instr.to_string(), name, width = width),
+            None => writeln!(listing, "{:>width$}  {}", index, instr, width = width),
+        }
+        .unwrap();
+    }
+    listing
+}
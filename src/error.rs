@@ -1,3 +1,6 @@
+use crate::line_table::Span;
+use std::fmt;
+
 #[derive(Debug)]
 pub struct CompilationError {
     pos: (usize, usize),
@@ -19,6 +22,10 @@ impl CompilationError {
         }
     }
 
+    pub fn get_pos(&self) -> (usize, usize) {
+        self.pos
+    }
+
     pub fn get_err_code(&self) -> &ErrorCode {
         &self.err_code
     }
@@ -45,4 +52,309 @@ pub enum ErrorCode {
     ErrNotInitialized,
     ErrInvalidAssignment,
     ErrInvalidPrint,
+    ErrIncompleteComment,
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ErrorCode::ErrNoError => "no error",
+            ErrorCode::ErrStreamError => "unable to read the input",
+            ErrorCode::ErrEOF => "unexpected end of input",
+            ErrorCode::ErrInvalidInput => "invalid character",
+            ErrorCode::ErrInvalidIdentifier => "invalid identifier",
+            ErrorCode::ErrIntegerOverflow => "integer literal out of range",
+            ErrorCode::ErrNoBegin => "expected 'begin'",
+            ErrorCode::ErrNoEnd => "expected 'end'",
+            ErrorCode::ErrNeedIdentifier => "expected an identifier",
+            ErrorCode::ErrConstantNeedValue => "a constant needs a value",
+            ErrorCode::ErrNoSemicolon => "expected ';'",
+            ErrorCode::ErrInvalidVariableDeclaration => "invalid variable declaration",
+            ErrorCode::ErrIncompleteExpression => "incomplete expression",
+            ErrorCode::ErrNotDeclared => "undeclared identifier",
+            ErrorCode::ErrAssignToConstant => "cannot assign to a constant",
+            ErrorCode::ErrDuplicateDeclaration => "duplicate declaration",
+            ErrorCode::ErrNotInitialized => "variable used before it is assigned",
+            ErrorCode::ErrInvalidAssignment => "invalid assignment",
+            ErrorCode::ErrInvalidPrint => "invalid print statement",
+            ErrorCode::ErrIncompleteComment => "unterminated comment",
+        })
+    }
+}
+
+// Warnings never stop the compilation, they are collected by the analyzer instead.
+#[derive(Debug, Clone)]
+pub struct CompilationWarning {
+    pos: (usize, usize),
+    warn_code: WarningCode,
+    name: String,
+}
+
+impl CompilationWarning {
+    pub fn new_packed(ptr: (usize, usize), warn: WarningCode, name: String) -> CompilationWarning {
+        CompilationWarning {
+            pos: ptr,
+            warn_code: warn,
+            name,
+        }
+    }
+
+    pub fn get_pos(&self) -> (usize, usize) {
+        self.pos
+    }
+
+    pub fn get_warn_code(&self) -> &WarningCode {
+        &self.warn_code
+    }
+
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    // The warning without its position, e.g. for editors showing it in place.
+    pub fn message(&self) -> String {
+        let message = match self.warn_code {
+            WarningCode::WarnUnusedConstant => "constant is never used",
+            WarningCode::WarnUnusedVariable => "variable is assigned but never read",
+            WarningCode::WarnNeverAssigned => "variable is never assigned",
+            WarningCode::WarnSelfAssignment => "variable is assigned to itself",
+        };
+        format!("`{}`: {} [-W{}]", self.name, message, self.warn_code.name())
+    }
+}
+
+impl fmt::Display for CompilationWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.pos.0 + 1, self.pos.1 + 1, self.message())
+    }
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum WarningCode {
+    WarnUnusedConstant,
+    WarnUnusedVariable,
+    WarnNeverAssigned,
+    WarnSelfAssignment,
+}
+
+impl WarningCode {
+    pub const ALL: [WarningCode; 4] = [
+        WarningCode::WarnUnusedConstant,
+        WarningCode::WarnUnusedVariable,
+        WarningCode::WarnNeverAssigned,
+        WarningCode::WarnSelfAssignment,
+    ];
+
+    // The name used by the -W command line option.
+    pub fn name(&self) -> &'static str {
+        match self {
+            WarningCode::WarnUnusedConstant => "unused-const",
+            WarningCode::WarnUnusedVariable => "unused-var",
+            WarningCode::WarnNeverAssigned => "never-assigned",
+            WarningCode::WarnSelfAssignment => "self-assign",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<WarningCode> {
+        WarningCode::ALL.iter().copied().find(|code| code.name() == name)
+    }
+}
+
+#[derive(Debug)]
+pub struct RuntimeError {
+    pc: usize,
+    span: Option<Span>,
+    err_code: RuntimeErrorCode,
+}
+
+impl RuntimeError {
+    pub fn new(pc: usize, err: RuntimeErrorCode) -> RuntimeError {
+        RuntimeError { pc, span: None, err_code: err }
+    }
+
+    // Source position of the failing instruction, when a line table is known.
+    pub fn with_span(self, span: Option<Span>) -> RuntimeError {
+        RuntimeError { span, ..self }
+    }
+
+    pub fn get_pc(&self) -> usize {
+        self.pc
+    }
+
+    pub fn get_span(&self) -> Option<&Span> {
+        self.span.as_ref()
+    }
+
+    pub fn get_err_code(&self) -> &RuntimeErrorCode {
+        &self.err_code
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let message = match self.err_code {
+            RuntimeErrorCode::ErrIllegalInstruction => "illegal instruction",
+            RuntimeErrorCode::ErrStackUnderflow => "stack underflow",
+            RuntimeErrorCode::ErrInvalidSlot => "invalid slot",
+            RuntimeErrorCode::ErrDivisionByZero => "division by zero",
+            RuntimeErrorCode::ErrOutput => "unable to write output",
+            RuntimeErrorCode::ErrInstructionLimit => "instruction limit exceeded",
+            RuntimeErrorCode::ErrStackLimit => "stack limit exceeded",
+            RuntimeErrorCode::ErrOutputLimit => "output limit exceeded",
+        };
+        match &self.span {
+            Some(span) => write!(f, "{} at {}", message, span),
+            None => write!(f, "{} at instruction {}", message, self.pc),
+        }
+    }
+}
+
+#[derive(PartialEq, Debug)]
+pub enum RuntimeErrorCode {
+    ErrIllegalInstruction,
+    ErrStackUnderflow,
+    ErrInvalidSlot,
+    ErrDivisionByZero,
+    ErrOutput,
+    ErrInstructionLimit,
+    ErrStackLimit,
+    ErrOutputLimit,
+}
+
+#[derive(Debug)]
+pub struct BytecodeError {
+    offset: usize,
+    err_code: BytecodeErrorCode,
+}
+
+impl BytecodeError {
+    pub fn new(offset: usize, err: BytecodeErrorCode) -> BytecodeError {
+        BytecodeError { offset, err_code: err }
+    }
+
+    pub fn get_offset(&self) -> usize {
+        self.offset
+    }
+
+    pub fn get_err_code(&self) -> &BytecodeErrorCode {
+        &self.err_code
+    }
+}
+
+impl fmt::Display for BytecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let message = match self.err_code {
+            BytecodeErrorCode::ErrBadMagic => "not a miniplc0 bytecode file",
+            BytecodeErrorCode::ErrUnsupportedVersion => "unsupported format version",
+            BytecodeErrorCode::ErrUnknownFlags => "unknown header flags",
+            BytecodeErrorCode::ErrTruncated => "unexpected end of file",
+            BytecodeErrorCode::ErrInvalidOpcode => "invalid opcode",
+            BytecodeErrorCode::ErrInvalidSlot => "slot out of range",
+            BytecodeErrorCode::ErrInvalidHeader => "inconsistent header",
+            BytecodeErrorCode::ErrInvalidDebugInfo => "invalid debug section",
+            BytecodeErrorCode::ErrTrailingData => "trailing data after the program",
+        };
+        write!(f, "{} at byte {}", message, self.offset)
+    }
+}
+
+#[derive(PartialEq, Debug)]
+pub enum BytecodeErrorCode {
+    ErrBadMagic,
+    ErrUnsupportedVersion,
+    ErrUnknownFlags,
+    ErrTruncated,
+    ErrInvalidOpcode,
+    ErrInvalidSlot,
+    ErrInvalidHeader,
+    ErrInvalidDebugInfo,
+    ErrTrailingData,
+}
+
+#[derive(Debug)]
+pub struct AssemblyError {
+    line: usize,
+    err_code: AssemblyErrorCode,
+}
+
+impl AssemblyError {
+    pub fn new(line: usize, err: AssemblyErrorCode) -> AssemblyError {
+        AssemblyError { line, err_code: err }
+    }
+
+    pub fn get_line(&self) -> usize {
+        self.line
+    }
+
+    pub fn get_err_code(&self) -> &AssemblyErrorCode {
+        &self.err_code
+    }
+}
+
+impl fmt::Display for AssemblyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let message = match self.err_code {
+            AssemblyErrorCode::ErrUnknownMnemonic => "unknown mnemonic",
+            AssemblyErrorCode::ErrMissingOperand => "missing operand",
+            AssemblyErrorCode::ErrUnexpectedOperand => "unexpected operand",
+            AssemblyErrorCode::ErrInvalidOperand => "invalid operand",
+            AssemblyErrorCode::ErrInvalidLabel => "invalid label",
+            AssemblyErrorCode::ErrDuplicateLabel => "duplicate label",
+            AssemblyErrorCode::ErrUndefinedLabel => "undefined label",
+        };
+        write!(f, "line {}: {}", self.line, message)
+    }
+}
+
+#[derive(PartialEq, Debug)]
+pub enum AssemblyErrorCode {
+    ErrUnknownMnemonic,
+    ErrMissingOperand,
+    ErrUnexpectedOperand,
+    ErrInvalidOperand,
+    ErrInvalidLabel,
+    ErrDuplicateLabel,
+    ErrUndefinedLabel,
+}
+
+#[derive(Debug)]
+pub struct CodegenError {
+    index: usize,
+    err_code: CodegenErrorCode,
+}
+
+impl CodegenError {
+    pub fn new(index: usize, err: CodegenErrorCode) -> CodegenError {
+        CodegenError { index, err_code: err }
+    }
+
+    pub fn get_index(&self) -> usize {
+        self.index
+    }
+
+    pub fn get_err_code(&self) -> &CodegenErrorCode {
+        &self.err_code
+    }
+}
+
+impl fmt::Display for CodegenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let message = match self.err_code {
+            CodegenErrorCode::ErrIllegalInstruction => "illegal instruction",
+            CodegenErrorCode::ErrStackUnderflow => "stack underflow",
+            CodegenErrorCode::ErrInvalidSlot => "invalid slot",
+            CodegenErrorCode::ErrTooLarge => "program too large for the target",
+        };
+        write!(f, "{} at instruction {}", message, self.index)
+    }
+}
+
+// The backends only translate code the VM could run to the end, and which
+// fits into the target's limits.
+#[derive(PartialEq, Debug)]
+pub enum CodegenErrorCode {
+    ErrIllegalInstruction,
+    ErrStackUnderflow,
+    ErrInvalidSlot,
+    ErrTooLarge,
 }
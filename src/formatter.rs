@@ -0,0 +1,188 @@
+use crate::{
+    error::CompilationError,
+    tokenizer::{tokenize_with_comments, Comment, Token, TokenType},
+};
+
+const INDENT: &str = "    ";
+
+enum Piece {
+    Token(Token),
+    Comment(Comment),
+}
+
+// Where the formatter is relative to `begin` and `end`.
+#[derive(PartialEq)]
+enum Part {
+    Before,
+    Body,
+    After,
+}
+
+struct Formatter {
+    output: String,
+    line: String,
+    part: Part,
+    previous: Option<Token>,
+    // Whether the previous token is a unary sign.
+    after_sign: bool,
+    // Source line the last piece written ends on.
+    last_line: Option<usize>,
+    // Set while a statement goes on after a line comment inside it.
+    continued: bool,
+}
+
+// Re-emits a program in the canonical layout: `begin` and `end` on lines of
+// their own, one statement per line indented by four spaces, operators
+// surrounded by spaces and signs attached to their operands. Comments stay
+// where they were, either after the code on its line or on lines of their
+// own, and one blank line is kept wherever the body had some. Only the layout
+// changes, so programs which don't compile are formatted as well as long as
+// they can be tokenized.
+pub fn format_source(source: &str) -> Result<String, CompilationError> {
+    let (tokens, comments) = tokenize_with_comments(source)?;
+    let mut pieces: Vec<Piece> = tokens.into_iter().map(Piece::Token).collect();
+    pieces.extend(comments.into_iter().map(Piece::Comment));
+    pieces.sort_by_key(|piece| match piece {
+        Piece::Token(token) => token.get_start_pos(),
+        Piece::Comment(comment) => comment.span.start,
+    });
+
+    let mut formatter = Formatter {
+        output: String::new(),
+        line: String::new(),
+        part: Part::Before,
+        previous: None,
+        after_sign: false,
+        last_line: None,
+        continued: false,
+    };
+    for piece in pieces {
+        match piece {
+            Piece::Token(token) => formatter.token(token),
+            Piece::Comment(comment) => formatter.comment(comment),
+        }
+    }
+    formatter.finish_line();
+    Ok(formatter.output)
+}
+
+impl Formatter {
+    fn token(&mut self, token: Token) {
+        let source_line = token.get_start_pos().0;
+        match (token.get_type(), &self.part) {
+            (TokenType::Begin, Part::Before) => {
+                self.finish_line();
+                self.line.push_str("begin");
+                self.finish_line();
+                self.part = Part::Body;
+            }
+            (TokenType::End, Part::Body) => {
+                self.finish_line();
+                self.line.push_str("end");
+                self.part = Part::After;
+            }
+            _ => {
+                let unary = is_unary(self.previous.as_ref(), &token);
+                if self.line.is_empty() {
+                    self.start_line(source_line);
+                } else if !self.after_sign && self.previous.as_ref().is_some_and(|prev| needs_space(prev, &token)) {
+                    self.line.push(' ');
+                }
+                self.after_sign = unary;
+                self.line.push_str(&token.to_string());
+                if *token.get_type() == TokenType::Semicolon && self.part != Part::After {
+                    self.continued = false;
+                    self.previous = None;
+                    self.last_line = Some(source_line);
+                    return self.finish_line();
+                }
+            }
+        }
+        self.previous = Some(token);
+        self.last_line = Some(source_line);
+    }
+
+    fn comment(&mut self, comment: Comment) {
+        let is_line_comment = comment.text.starts_with("//");
+        let trailing = self.last_line == Some(comment.span.start.0);
+        if trailing && self.line.is_empty() && self.output.ends_with('\n') {
+            // After a statement already written out.
+            self.output.pop();
+            self.output.push_str("  ");
+            self.output.push_str(&comment.text);
+            self.output.push('\n');
+        } else if trailing {
+            self.line.push_str(if is_line_comment { "  " } else { " " });
+            self.line.push_str(&comment.text);
+            if is_line_comment {
+                self.break_statement();
+            }
+        } else {
+            if !self.line.is_empty() {
+                self.break_statement();
+            }
+            self.start_line(comment.span.start.0);
+            self.line.push_str(&comment.text);
+            if is_line_comment || self.previous.is_none() {
+                self.break_statement();
+            }
+        }
+        self.last_line = Some(comment.span.end.0);
+    }
+
+    // Ends the current line in the middle of a statement, which then goes on
+    // indented one more level.
+    fn break_statement(&mut self) {
+        self.continued = self.previous.is_some();
+        self.finish_line();
+    }
+
+    // Indents a new line, after a blank one if the source had any between
+    // this and the last piece of the body.
+    fn start_line(&mut self, source_line: usize) {
+        let blank = self.last_line.is_some_and(|last| source_line > last + 1);
+        if blank && self.part == Part::Body && !self.output.ends_with("begin\n") && !self.output.ends_with("\n\n") {
+            self.output.push('\n');
+        }
+        if self.part == Part::Body {
+            self.line.push_str(INDENT);
+            if self.continued {
+                self.line.push_str(INDENT);
+            }
+        }
+    }
+
+    fn finish_line(&mut self) {
+        if !self.line.is_empty() {
+            self.output.push_str(&self.line);
+            self.output.push('\n');
+            self.line.clear();
+        }
+    }
+}
+
+// Signs directly in front of an operand are unary and stick to it.
+fn is_unary(previous: Option<&Token>, token: &Token) -> bool {
+    matches!(token.get_type(), TokenType::PlusSign | TokenType::MinusSign)
+        && previous.is_none_or(|previous| {
+            matches!(
+                previous.get_type(),
+                TokenType::EqualSign
+                    | TokenType::LeftBracket
+                    | TokenType::PlusSign
+                    | TokenType::MinusSign
+                    | TokenType::MultiplicationSign
+                    | TokenType::DivisionSign
+            )
+        })
+}
+
+fn needs_space(previous: &Token, token: &Token) -> bool {
+    !matches!(
+        (previous.get_type(), token.get_type()),
+        (_, TokenType::RightBracket)
+            | (_, TokenType::Semicolon)
+            | (TokenType::LeftBracket, _)
+            | (TokenType::Print, TokenType::LeftBracket)
+    )
+}
@@ -0,0 +1,550 @@
+use crate::{
+    analyzer::{declarations, Instruction, Operation},
+    cfg::ControlFlowGraph,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+};
+
+// Three-address SSA form of a program. Every instruction defines the value that
+// shares its index, so `insts[v.0]` is the definition of `v`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SsaValue(pub usize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum SsaInst {
+    Const(i32),
+    Binary(BinaryOp, SsaValue, SsaValue),
+    // One operand per predecessor block.
+    Phi(Vec<(usize, SsaValue)>),
+    // Defines no usable value.
+    Print(SsaValue),
+    // Writes a declared slot, defining no usable value either.
+    Store(usize, SsaValue),
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SsaBlock {
+    pub insts: Vec<SsaValue>,
+    pub predecessors: Vec<usize>,
+    pub successors: Vec<usize>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SsaProgram {
+    pub insts: Vec<SsaInst>,
+    pub blocks: Vec<SsaBlock>,
+    // The stack instruction each value was built from.
+    pub origins: Vec<usize>,
+}
+
+impl BinaryOp {
+    pub fn from_operation(op: Operation) -> Option<BinaryOp> {
+        match op {
+            Operation::ADD => Some(BinaryOp::Add),
+            Operation::SUB => Some(BinaryOp::Sub),
+            Operation::MUL => Some(BinaryOp::Mul),
+            Operation::DIV => Some(BinaryOp::Div),
+            _ => None,
+        }
+    }
+
+    pub fn to_operation(self) -> Operation {
+        match self {
+            BinaryOp::Add => Operation::ADD,
+            BinaryOp::Sub => Operation::SUB,
+            BinaryOp::Mul => Operation::MUL,
+            BinaryOp::Div => Operation::DIV,
+        }
+    }
+}
+
+impl SsaInst {
+    pub fn has_value(&self) -> bool {
+        !matches!(self, SsaInst::Print(_) | SsaInst::Store(..))
+    }
+
+    pub fn operands(&self) -> Vec<SsaValue> {
+        match self {
+            SsaInst::Const(_) => Vec::new(),
+            SsaInst::Binary(_, lhs, rhs) => vec![*lhs, *rhs],
+            SsaInst::Phi(operands) => operands.iter().map(|(_, value)| *value).collect(),
+            SsaInst::Print(value) | SsaInst::Store(_, value) => vec![*value],
+        }
+    }
+}
+
+impl SsaProgram {
+    // Stack positions play the role of variables: a slot is the stack entry its
+    // declaration pushed, so LOD/STO read and write a position below the top.
+    // The values slots hold once declared are recorded as stores.
+    pub fn from_instructions(instructions: &[Instruction]) -> Result<SsaProgram, String> {
+        let cfg = ControlFlowGraph::new(instructions);
+        let mut builder = Builder {
+            program: SsaProgram {
+                insts: Vec::new(),
+                blocks: cfg
+                    .blocks
+                    .iter()
+                    .map(|block| SsaBlock {
+                        insts: Vec::new(),
+                        predecessors: block.predecessors.clone(),
+                        successors: block.successors.clone(),
+                    })
+                    .collect(),
+                origins: Vec::new(),
+            },
+            origin: 0,
+            current_def: vec![HashMap::new(); cfg.blocks.len()],
+            incomplete_phis: vec![Vec::new(); cfg.blocks.len()],
+            sealed: vec![false; cfg.blocks.len()],
+            filled: vec![false; cfg.blocks.len()],
+        };
+        let declarations = declarations(instructions);
+        let mut declared = 0;
+        let mut entry_height: Vec<Option<usize>> = vec![None; cfg.blocks.len()];
+        if !cfg.blocks.is_empty() {
+            entry_height[0] = Some(0);
+        }
+
+        for (b, block) in cfg.blocks.iter().enumerate() {
+            let mut height = match entry_height[b] {
+                Some(height) => height,
+                None => continue, // unreachable
+            };
+            if block.predecessors.iter().all(|&p| builder.filled[p]) {
+                builder.seal(b);
+            }
+            for (index, instr) in instructions.iter().enumerate().take(block.end).skip(block.start) {
+                let (pops, _) = instr.0.stack_effect();
+                if height < pops {
+                    return Err(format!("stack underflow at instruction {}", index));
+                }
+                builder.origin = index;
+                match instr.0 {
+                    Operation::LIT => {
+                        let value = builder.push_inst(b, SsaInst::Const(instr.1));
+                        builder.write(b, height, value);
+                        height += 1;
+                    }
+                    Operation::LOD => {
+                        let slot = builder.check_slot(instr, index, height)?;
+                        let value = builder.read(b, slot);
+                        builder.write(b, height, value);
+                        height += 1;
+                    }
+                    Operation::STO => {
+                        let slot = builder.check_slot(instr, index, height - 1)?;
+                        let value = builder.read(b, height - 1);
+                        builder.write(b, slot, value);
+                        if slot < declared {
+                            builder.push_inst(b, SsaInst::Store(slot, value));
+                        }
+                        height -= 1;
+                    }
+                    Operation::WRT => {
+                        let value = builder.read(b, height - 1);
+                        builder.push_inst(b, SsaInst::Print(value));
+                        height -= 1;
+                    }
+                    Operation::ADD | Operation::SUB | Operation::MUL | Operation::DIV => {
+                        let rhs = builder.read(b, height - 1);
+                        let lhs = builder.read(b, height - 2);
+                        let op = BinaryOp::from_operation(instr.0).unwrap();
+                        let value = builder.push_inst(b, SsaInst::Binary(op, lhs, rhs));
+                        height -= 1;
+                        builder.write(b, height - 1, value);
+                    }
+                    Operation::ILL => return Err(format!("illegal instruction at {}", index)),
+                }
+                if declarations.get(declared) == Some(&index) {
+                    let value = builder.read(b, declared);
+                    builder.push_inst(b, SsaInst::Store(declared, value));
+                    declared += 1;
+                }
+            }
+            for &successor in &block.successors {
+                match entry_height[successor] {
+                    Some(expected) if expected != height => {
+                        return Err(format!("stack height mismatch entering block {}", successor))
+                    }
+                    _ => entry_height[successor] = Some(height),
+                }
+            }
+            builder.filled[b] = true;
+            for s in 0..cfg.blocks.len() {
+                if !builder.sealed[s] && cfg.blocks[s].predecessors.iter().all(|&p| builder.filled[p]) {
+                    builder.seal(s);
+                }
+            }
+        }
+        Ok(builder.program)
+    }
+
+    // Only instructions placed in a block count, passes may leave others behind.
+    pub fn use_counts(&self) -> Vec<usize> {
+        let mut counts = vec![0; self.insts.len()];
+        for value in self.blocks.iter().flat_map(|block| &block.insts) {
+            for operand in self.insts[value.0].operands() {
+                counts[operand.0] += 1;
+            }
+        }
+        counts
+    }
+
+    pub fn verify(&self) -> Result<(), String> {
+        let mut block_of = vec![None; self.insts.len()];
+        for (b, block) in self.blocks.iter().enumerate() {
+            for (position, value) in block.insts.iter().enumerate() {
+                if value.0 >= self.insts.len() {
+                    return Err(format!("block {} lists undefined value {}", b, value));
+                }
+                if block_of[value.0].is_some() {
+                    return Err(format!("{} is placed more than once", value));
+                }
+                block_of[value.0] = Some((b, position));
+            }
+        }
+        let dominators = self.dominators();
+        for (b, block) in self.blocks.iter().enumerate() {
+            for (position, value) in block.insts.iter().enumerate() {
+                let inst = &self.insts[value.0];
+                if let SsaInst::Phi(operands) = inst {
+                    if position > 0 && !matches!(self.insts[block.insts[position - 1].0], SsaInst::Phi(_)) {
+                        return Err(format!("phi {} is not at the start of block {}", value, b));
+                    }
+                    let mut from: Vec<usize> = operands.iter().map(|(p, _)| *p).collect();
+                    let mut predecessors = block.predecessors.clone();
+                    from.sort_unstable();
+                    predecessors.sort_unstable();
+                    if from != predecessors {
+                        return Err(format!("phi {} does not match the predecessors of block {}", value, b));
+                    }
+                }
+                // A phi uses its operands at the end of the matching predecessor.
+                let uses: Vec<(usize, SsaValue)> = match inst {
+                    SsaInst::Phi(operands) => operands.clone(),
+                    _ => inst.operands().into_iter().map(|operand| (b, operand)).collect(),
+                };
+                for (use_block, operand) in uses {
+                    let (def_block, def_position) = match block_of.get(operand.0).copied().flatten() {
+                        Some(def) if self.insts[operand.0].has_value() => def,
+                        _ => return Err(format!("{} uses {} which is not a value", value, operand)),
+                    };
+                    let dominated = if def_block == b && !matches!(inst, SsaInst::Phi(_)) {
+                        def_position < position
+                    } else {
+                        dominators[use_block].contains(&def_block)
+                    };
+                    if !dominated {
+                        return Err(format!("definition of {} does not dominate its use in {}", operand, value));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Straight-line stack code. Declared slots keep their positions at the
+    // bottom of the stack, followed by temporary slots for values used more than
+    // once; everything else is recomputed at its single use, or loaded from the
+    // slot holding it, and unused values are dropped. Divisions which may fail
+    // are computed into a temporary where they stand all the same, so that
+    // their error still comes after the same output.
+    pub fn to_instructions(&self) -> Result<Vec<Instruction>, String> {
+        Ok(self.to_instructions_with_origins()?.0)
+    }
+
+    // Also returns, for each instruction, the stack instruction it comes from.
+    pub fn to_instructions_with_origins(&self) -> Result<(Vec<Instruction>, Vec<usize>), String> {
+        for (b, block) in self.blocks.iter().enumerate() {
+            if block.successors.iter().any(|&s| s != b + 1) {
+                return Err(String::from("branches need jump instructions to be lowered"));
+            }
+            if block.insts.iter().any(|v| matches!(self.insts[v.0], SsaInst::Phi(_))) {
+                return Err(String::from("phi nodes need jump instructions to be lowered"));
+            }
+        }
+        let order: Vec<SsaValue> = self.blocks.iter().flat_map(|block| block.insts.iter().copied()).collect();
+        let mut counts = vec![0; self.insts.len()];
+        let mut stores = Vec::new();
+        for value in &order {
+            match self.insts[value.0] {
+                SsaInst::Store(slot, _) if slot == stores.len() => stores.push(*value),
+                SsaInst::Store(..) => (),
+                ref inst => inst.operands().iter().for_each(|operand| counts[operand.0] += 1),
+            }
+        }
+        // A value stored right away is computed by the store and then held in
+        // the slot instead.
+        let temporaries: Vec<SsaValue> = order
+            .iter()
+            .enumerate()
+            .filter(|(i, value)| {
+                let shared = counts[value.0] > 1 && !matches!(self.insts[value.0], SsaInst::Const(_));
+                let next = order.get(i + 1).map(|next| &self.insts[next.0]);
+                let stored = matches!(next, Some(SsaInst::Store(_, v)) if v == *value);
+                (shared || self.may_fail(**value)) && !stored
+            })
+            .map(|(_, value)| *value)
+            .collect();
+
+        let mut lowering = Lowering {
+            program: self,
+            code: Vec::new(),
+            origins: Vec::new(),
+            temps: HashMap::new(),
+            held: HashMap::new(),
+        };
+        let mut declared = 0;
+        let mut frame = false;
+        for value in &order {
+            let origin = self.origins[value.0];
+            match self.insts[value.0] {
+                SsaInst::Print(operand) => {
+                    lowering.emit_value(operand, origin);
+                    lowering.push(Instruction(Operation::WRT, 0), origin);
+                }
+                SsaInst::Store(slot, operand) => {
+                    lowering.emit_value(operand, origin);
+                    if slot == declared && !frame {
+                        declared += 1;
+                    } else {
+                        lowering.push(Instruction(Operation::STO, slot as i32), origin);
+                    }
+                    lowering.held.retain(|_, held| *held != slot as i32);
+                    lowering.held.insert(operand, slot as i32);
+                }
+                _ if temporaries.contains(value) => {
+                    // The slots not declared yet are pushed along with the
+                    // temporaries, which have to come above them.
+                    if !frame {
+                        for store in &stores[declared..] {
+                            lowering.push(Instruction(Operation::LIT, 0), self.origins[store.0]);
+                        }
+                        for temp in &temporaries {
+                            lowering.push(Instruction(Operation::LIT, 0), self.origins[temp.0]);
+                        }
+                        frame = true;
+                    }
+                    lowering.emit_value(*value, origin);
+                    let slot = (stores.len() + lowering.temps.len()) as i32;
+                    lowering.push(Instruction(Operation::STO, slot), origin);
+                    lowering.temps.insert(*value, slot);
+                }
+                _ => (),
+            }
+        }
+        Ok((lowering.code, lowering.origins))
+    }
+
+    // A division by anything but a non-zero constant.
+    pub fn may_fail(&self, value: SsaValue) -> bool {
+        match self.insts[value.0] {
+            SsaInst::Binary(BinaryOp::Div, _, rhs) => !matches!(self.insts[rhs.0], SsaInst::Const(n) if n != 0),
+            _ => false,
+        }
+    }
+
+    // dominators()[b] holds every block that dominates b, b included.
+    pub fn dominators(&self) -> Vec<HashSet<usize>> {
+        let all: HashSet<usize> = (0..self.blocks.len()).collect();
+        let mut dominators: Vec<HashSet<usize>> = vec![all; self.blocks.len()];
+        if let Some(entry) = dominators.first_mut() {
+            *entry = [0].iter().copied().collect();
+        }
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for b in 1..self.blocks.len() {
+                let mut new = self.blocks[b]
+                    .predecessors
+                    .iter()
+                    .map(|p| dominators[*p].clone())
+                    .reduce(|a, b| a.intersection(&b).copied().collect())
+                    .unwrap_or_default();
+                new.insert(b);
+                if new != dominators[b] {
+                    dominators[b] = new;
+                    changed = true;
+                }
+            }
+        }
+        dominators
+    }
+}
+
+// SSA construction after Braun et al., "Simple and Efficient Construction of
+// Static Single Assignment Form". A block is sealed once all its predecessors
+// are filled; reads in unsealed blocks leave phis to be completed later.
+struct Builder {
+    program: SsaProgram,
+    // The instruction being translated.
+    origin: usize,
+    current_def: Vec<HashMap<usize, SsaValue>>,
+    incomplete_phis: Vec<Vec<(usize, SsaValue)>>,
+    sealed: Vec<bool>,
+    filled: Vec<bool>,
+}
+
+impl Builder {
+    fn push_inst(&mut self, block: usize, inst: SsaInst) -> SsaValue {
+        let value = SsaValue(self.program.insts.len());
+        self.program.insts.push(inst);
+        self.program.origins.push(self.origin);
+        self.program.blocks[block].insts.push(value);
+        value
+    }
+
+    fn check_slot(&self, instr: &Instruction, index: usize, height: usize) -> Result<usize, String> {
+        if instr.1 < 0 || instr.1 as usize >= height {
+            return Err(format!("slot {} out of range at instruction {}", instr.1, index));
+        }
+        Ok(instr.1 as usize)
+    }
+
+    fn write(&mut self, block: usize, position: usize, value: SsaValue) {
+        self.current_def[block].insert(position, value);
+    }
+
+    fn read(&mut self, block: usize, position: usize) -> SsaValue {
+        if let Some(value) = self.current_def[block].get(&position) {
+            return *value;
+        }
+        let predecessors = self.program.blocks[block].predecessors.clone();
+        let value = if !self.sealed[block] {
+            let phi = self.new_phi(block);
+            self.incomplete_phis[block].push((position, phi));
+            phi
+        } else if predecessors.len() == 1 {
+            self.read(predecessors[0], position)
+        } else {
+            let phi = self.new_phi(block);
+            self.write(block, position, phi);
+            self.add_phi_operands(block, position, phi);
+            phi
+        };
+        self.write(block, position, value);
+        value
+    }
+
+    fn new_phi(&mut self, block: usize) -> SsaValue {
+        let value = SsaValue(self.program.insts.len());
+        self.program.insts.push(SsaInst::Phi(Vec::new()));
+        self.program.origins.push(self.origin);
+        let program = &mut self.program;
+        let phi_count = program.blocks[block]
+            .insts
+            .iter()
+            .take_while(|v| matches!(program.insts[v.0], SsaInst::Phi(_)))
+            .count();
+        program.blocks[block].insts.insert(phi_count, value);
+        value
+    }
+
+    fn add_phi_operands(&mut self, block: usize, position: usize, phi: SsaValue) {
+        let predecessors = self.program.blocks[block].predecessors.clone();
+        let operands = predecessors.into_iter().map(|p| (p, self.read(p, position))).collect();
+        self.program.insts[phi.0] = SsaInst::Phi(operands);
+    }
+
+    fn seal(&mut self, block: usize) {
+        for (position, phi) in std::mem::take(&mut self.incomplete_phis[block]) {
+            self.add_phi_operands(block, position, phi);
+        }
+        self.sealed[block] = true;
+    }
+}
+
+// Stack code being emitted by to_instructions.
+struct Lowering<'a> {
+    program: &'a SsaProgram,
+    code: Vec<Instruction>,
+    origins: Vec<usize>,
+    // Values kept in temporary slots, and values a declared slot holds.
+    temps: HashMap<SsaValue, i32>,
+    held: HashMap<SsaValue, i32>,
+}
+
+impl Lowering<'_> {
+    fn push(&mut self, instr: Instruction, origin: usize) {
+        self.code.push(instr);
+        self.origins.push(origin);
+    }
+
+    // Loads count as part of the instruction using the value, and so do
+    // constants, which are cheaper to push again.
+    fn emit_value(&mut self, value: SsaValue, used_at: usize) {
+        let held = match self.program.insts[value.0] {
+            SsaInst::Const(_) => None,
+            _ => self.held.get(&value),
+        };
+        if let Some(&slot) = self.temps.get(&value).or(held) {
+            self.push(Instruction(Operation::LOD, slot), used_at);
+            return;
+        }
+        let origin = self.program.origins[value.0];
+        match &self.program.insts[value.0] {
+            SsaInst::Const(n) => self.push(Instruction(Operation::LIT, *n), used_at),
+            SsaInst::Binary(op, lhs, rhs) => {
+                self.emit_value(*lhs, origin);
+                self.emit_value(*rhs, origin);
+                self.push(Instruction(op.to_operation(), 0), origin);
+            }
+            SsaInst::Phi(_) | SsaInst::Print(_) | SsaInst::Store(..) => panic!("Cannot recompute {}.", value),
+        }
+    }
+}
+
+impl fmt::Display for SsaValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "%{}", self.0)
+    }
+}
+
+impl fmt::Display for BinaryOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            BinaryOp::Add => "add",
+            BinaryOp::Sub => "sub",
+            BinaryOp::Mul => "mul",
+            BinaryOp::Div => "div",
+        })
+    }
+}
+
+impl fmt::Display for SsaProgram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (b, block) in self.blocks.iter().enumerate() {
+            if block.predecessors.is_empty() {
+                writeln!(f, "b{}:", b)?;
+            } else {
+                let predecessors: Vec<String> = block.predecessors.iter().map(|p| format!("b{}", p)).collect();
+                writeln!(f, "b{}: ; preds = {}", b, predecessors.join(", "))?;
+            }
+            for value in &block.insts {
+                match &self.insts[value.0] {
+                    SsaInst::Const(n) => writeln!(f, "    {} = const {}", value, n)?,
+                    SsaInst::Binary(op, lhs, rhs) => writeln!(f, "    {} = {} {}, {}", value, op, lhs, rhs)?,
+                    SsaInst::Phi(operands) => {
+                        let operands: Vec<String> =
+                            operands.iter().map(|(p, v)| format!("[b{}: {}]", p, v)).collect();
+                        writeln!(f, "    {} = phi {}", value, operands.join(", "))?
+                    }
+                    SsaInst::Print(operand) => writeln!(f, "    print {}", operand)?,
+                    SsaInst::Store(slot, operand) => writeln!(f, "    store s{}, {}", slot, operand)?,
+                }
+            }
+        }
+        Ok(())
+    }
+}
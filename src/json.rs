@@ -0,0 +1,252 @@
+use std::fmt;
+
+// Just enough JSON for the language server's messages. Objects keep their keys
+// in order; numbers are f64 like in JavaScript.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    pub fn parse(text: &str) -> Result<Json, String> {
+        let mut parser = Parser { chars: text.chars().collect(), at: 0 };
+        let value = parser.value()?;
+        parser.whitespace();
+        match parser.chars.get(parser.at) {
+            None => Ok(value),
+            Some(ch) => Err(format!("unexpected '{}' after the value", ch)),
+        }
+    }
+
+    pub fn object(members: Vec<(&str, Json)>) -> Json {
+        Json::Object(members.into_iter().map(|(key, value)| (String::from(key), value)).collect())
+    }
+
+    pub fn string(text: &str) -> Json {
+        Json::String(String::from(text))
+    }
+
+    pub fn number(value: usize) -> Json {
+        Json::Number(value as f64)
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(members) => members.iter().find(|(name, _)| name == key).map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    // Follows a path of object keys.
+    pub fn at(&self, path: &[&str]) -> Option<&Json> {
+        path.iter().try_fold(self, |value, key| value.get(key))
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(text) => Some(text),
+            _ => None,
+        }
+    }
+
+    pub fn as_usize(&self) -> Option<usize> {
+        match self {
+            Json::Number(value) if *value >= 0.0 && value.fract() == 0.0 => Some(*value as usize),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Json::Bool(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&Vec<Json>> {
+        match self {
+            Json::Array(values) => Some(values),
+            _ => None,
+        }
+    }
+}
+
+struct Parser {
+    chars: Vec<char>,
+    at: usize,
+}
+
+impl Parser {
+    fn whitespace(&mut self) {
+        while self.chars.get(self.at).is_some_and(|ch| matches!(ch, ' ' | '\t' | '\n' | '\r')) {
+            self.at += 1;
+        }
+    }
+
+    fn next(&mut self) -> Result<char, String> {
+        let ch = *self.chars.get(self.at).ok_or("unexpected end of input")?;
+        self.at += 1;
+        Ok(ch)
+    }
+
+    fn literal(&mut self, word: &str, value: Json) -> Result<Json, String> {
+        for expected in word.chars() {
+            if self.next()? != expected {
+                return Err(format!("invalid literal, expected '{}'", word));
+            }
+        }
+        Ok(value)
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        self.whitespace();
+        match self.chars.get(self.at) {
+            Some('n') => self.literal("null", Json::Null),
+            Some('t') => self.literal("true", Json::Bool(true)),
+            Some('f') => self.literal("false", Json::Bool(false)),
+            Some('"') => self.string().map(Json::String),
+            Some('[') => {
+                self.at += 1;
+                let mut values = Vec::new();
+                self.whitespace();
+                if self.chars.get(self.at) == Some(&']') {
+                    self.at += 1;
+                    return Ok(Json::Array(values));
+                }
+                loop {
+                    values.push(self.value()?);
+                    self.whitespace();
+                    match self.next()? {
+                        ',' => (),
+                        ']' => return Ok(Json::Array(values)),
+                        ch => return Err(format!("expected ',' or ']', found '{}'", ch)),
+                    }
+                }
+            }
+            Some('{') => {
+                self.at += 1;
+                let mut members = Vec::new();
+                self.whitespace();
+                if self.chars.get(self.at) == Some(&'}') {
+                    self.at += 1;
+                    return Ok(Json::Object(members));
+                }
+                loop {
+                    self.whitespace();
+                    if self.chars.get(self.at) != Some(&'"') {
+                        return Err(String::from("expected a key"));
+                    }
+                    let key = self.string()?;
+                    self.whitespace();
+                    if self.next()? != ':' {
+                        return Err(String::from("expected ':' after a key"));
+                    }
+                    members.push((key, self.value()?));
+                    self.whitespace();
+                    match self.next()? {
+                        ',' => (),
+                        '}' => return Ok(Json::Object(members)),
+                        ch => return Err(format!("expected ',' or '}}', found '{}'", ch)),
+                    }
+                }
+            }
+            Some(ch) if *ch == '-' || ch.is_ascii_digit() => {
+                let start = self.at;
+                while self.chars.get(self.at).is_some_and(|ch| matches!(ch, '-' | '+' | '.' | 'e' | 'E' | '0'..='9')) {
+                    self.at += 1;
+                }
+                let number: String = self.chars[start..self.at].iter().collect();
+                number.parse().map(Json::Number).map_err(|_| format!("invalid number '{}'", number))
+            }
+            Some(ch) => Err(format!("unexpected '{}'", ch)),
+            None => Err(String::from("unexpected end of input")),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.at += 1;
+        let mut text = String::new();
+        loop {
+            match self.next()? {
+                '"' => return Ok(text),
+                '\\' => match self.next()? {
+                    'n' => text.push('\n'),
+                    't' => text.push('\t'),
+                    'r' => text.push('\r'),
+                    'b' => text.push('\u{8}'),
+                    'f' => text.push('\u{c}'),
+                    'u' => {
+                        let mut code = self.hex()?;
+                        // A surrogate pair spells a character beyond the BMP.
+                        if (0xd800..0xdc00).contains(&code) && self.chars[self.at..].starts_with(&['\\', 'u']) {
+                            self.at += 2;
+                            let low = self.hex()?;
+                            code = match low {
+                                0xdc00..=0xdfff => 0x10000 + ((code - 0xd800) << 10) + (low - 0xdc00),
+                                _ => 0xfffd,
+                            };
+                        }
+                        text.push(char::from_u32(code).unwrap_or('\u{fffd}'));
+                    }
+                    ch => text.push(ch),
+                },
+                ch => text.push(ch),
+            }
+        }
+    }
+
+    fn hex(&mut self) -> Result<u32, String> {
+        let digits: String = (0..4).map(|_| self.next()).collect::<Result<_, _>>()?;
+        u32::from_str_radix(&digits, 16).map_err(|_| format!("invalid escape '\\u{}'", digits))
+    }
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Json::Null => f.write_str("null"),
+            Json::Bool(value) => write!(f, "{}", value),
+            Json::Number(value) => write!(f, "{}", value),
+            Json::String(text) => {
+                f.write_str("\"")?;
+                for ch in text.chars() {
+                    match ch {
+                        '"' => f.write_str("\\\"")?,
+                        '\\' => f.write_str("\\\\")?,
+                        '\n' => f.write_str("\\n")?,
+                        '\r' => f.write_str("\\r")?,
+                        '\t' => f.write_str("\\t")?,
+                        ch if (ch as u32) < 0x20 => write!(f, "\\u{:04x}", ch as u32)?,
+                        ch => write!(f, "{}", ch)?,
+                    }
+                }
+                f.write_str("\"")
+            }
+            Json::Array(values) => {
+                f.write_str("[")?;
+                for (index, value) in values.iter().enumerate() {
+                    if index > 0 {
+                        f.write_str(",")?;
+                    }
+                    value.fmt(f)?;
+                }
+                f.write_str("]")
+            }
+            Json::Object(members) => {
+                f.write_str("{")?;
+                for (index, (key, value)) in members.iter().enumerate() {
+                    if index > 0 {
+                        f.write_str(",")?;
+                    }
+                    write!(f, "{}:{}", Json::String(key.clone()), value)?;
+                }
+                f.write_str("}")
+            }
+        }
+    }
+}
@@ -1,13 +1,67 @@
 mod analyzer;
 mod tokenizer;
 mod error;
+mod optimizer;
+mod cfg;
+mod dataflow;
+mod ir;
+mod vm;
+mod bytecode;
+mod assembler;
+mod backend;
+mod disassembler;
+mod debugger;
+mod line_table;
+mod repl;
+mod formatter;
+mod syntax;
+mod json;
+mod lsp;
+mod register_vm;
 
-pub use analyzer::analyze;
-pub use tokenizer::tokenize;
+pub use analyzer::{analyze, analyze_with_warnings, Instruction, Operation};
+pub use assembler::assemble;
+pub use backend::{emit_c, emit_js, emit_jvm, emit_llvm, emit_riscv, emit_wat, emit_x86_64, RiscvTarget};
+pub use bytecode::{compile, is_bytecode, Bytecode};
+pub use cfg::{BasicBlock, ControlFlowGraph};
+pub use debugger::Debugger;
+pub use disassembler::disassemble;
+pub use line_table::{LineTable, Span};
+pub use ir::{BinaryOp, SsaBlock, SsaInst, SsaProgram, SsaValue};
+pub use formatter::format_source;
+pub use error::{
+    AssemblyError, AssemblyErrorCode, BytecodeError, BytecodeErrorCode, CodegenError, CodegenErrorCode,
+    CompilationWarning, RuntimeError, RuntimeErrorCode, WarningCode,
+};
+pub use lsp::LanguageServer;
+pub use optimizer::{eliminate_common_subexpressions, eliminate_dead_code, optimize, optimize_bytecode};
+pub use register_vm::{lower, Operand, RegisterInstruction, RegisterMachine, RegisterProgram};
+pub use repl::Repl;
+pub use syntax::{lex, parse, NodeKind, SyntaxElement, SyntaxNode, SyntaxToken, SyntaxTree, Trivia, TriviaKind};
+pub use tokenizer::{tokenize, tokenize_source, tokenize_with_comments, Comment, Token, TokenType};
+pub use vm::{run, Limits, TraceFormat, VirtualMachine};
 
 #[cfg(test)]
 mod tests{
-    use crate::tokenizer::tokenize;
+    use crate::analyzer::{analyze, analyze_with_warnings, Analyzer, Instruction, Operation};
+    use crate::assembler::assemble;
+    use crate::backend::{emit_c, emit_js, emit_jvm, emit_llvm, emit_riscv, emit_wat, emit_x86_64, RiscvTarget};
+    use crate::bytecode::{compile, Bytecode};
+    use crate::cfg::ControlFlowGraph;
+    use crate::debugger::Debugger;
+    use crate::disassembler::disassemble;
+    use crate::error::{AssemblyErrorCode, BytecodeErrorCode, CodegenErrorCode, ErrorCode, RuntimeErrorCode, WarningCode};
+    use crate::ir::SsaProgram;
+    use crate::json::Json;
+    use crate::lsp::LanguageServer;
+    use crate::register_vm::{lower, RegisterMachine};
+    use crate::repl::Repl;
+    use crate::optimizer::{eliminate_dead_code, optimize, optimize_bytecode};
+    use crate::formatter::format_source;
+    use crate::syntax::{parse, NodeKind, SyntaxElement, TriviaKind};
+    use crate::tokenizer::{tokenize, tokenize_source, tokenize_with_comments};
+    use crate::vm::{run, Limits, TraceFormat, VirtualMachine};
+    use std::{path::Path, process::Command};
 
     #[test]
     fn test_tokenizer() {
@@ -16,4 +70,750 @@ mod tests{
             print!("{}", &token);
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_negation() {
+        // -x is computed as 0 - x.
+        let instructions = analyze(String::from("files/negation.plc0"));
+        let listing: Vec<String> = instructions.iter().map(|instr| instr.to_string()).collect();
+        assert_eq!(listing.join("; "), "LIT 3; LIT 0; LOD 0; SUB; LIT 0; LOD 1; SUB; LIT 1; ADD; WRT");
+    }
+
+    #[test]
+    fn test_declaration_without_value() {
+        // The ';' after `var a` is part of the declaration, so more can follow.
+        let instructions = analyze(String::from("files/declarations.plc0"));
+        let listing: Vec<String> = instructions.iter().map(|instr| instr.to_string()).collect();
+        assert_eq!(listing.join("; "), "LIT 0; LIT 1; LOD 1; STO 0; LOD 0; WRT");
+    }
+
+    #[test]
+    fn test_dead_code_elimination() {
+        let instructions = eliminate_dead_code(&analyze(String::from("files/dead_store.plc0")));
+        assert_eq!(
+            instructions,
+            vec![
+                Instruction(Operation::LIT, 0),
+                Instruction(Operation::LIT, 4),
+                Instruction(Operation::STO, 0),
+                Instruction(Operation::LOD, 0),
+                Instruction(Operation::WRT, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_warnings() {
+        let (_, warnings) = analyze_with_warnings(String::from("files/warnings.plc0"));
+        let found: Vec<_> = warnings
+            .iter()
+            .map(|warning| (warning.get_name(), *warning.get_warn_code()))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                ("k", WarningCode::WarnUnusedConstant),
+                ("b", WarningCode::WarnUnusedVariable),
+                ("n", WarningCode::WarnNeverAssigned),
+                ("a", WarningCode::WarnSelfAssignment),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_read_before_assignment() {
+        let mut analyzer = Analyzer::new(tokenize(String::from("files/uninitialized.plc0")));
+        let err = analyzer.analyze().unwrap_err();
+        assert_eq!(err.get_err_code(), &ErrorCode::ErrNotInitialized);
+        assert_eq!(err.get_pos(), (4, 19));
+        // Compiling reports it too, instead of panicking.
+        let err = compile(String::from("files/uninitialized.plc0")).unwrap_err();
+        assert_eq!((err.get_pos(), err.get_err_code()), ((4, 19), &ErrorCode::ErrNotInitialized));
+    }
+
+    #[test]
+    fn test_cfg_dot() {
+        let instructions = analyze(String::from("files/somhow.plc0"));
+        let cfg = ControlFlowGraph::new(&instructions);
+        assert_eq!(cfg.blocks.len(), 1);
+        assert_eq!((cfg.blocks[0].start, cfg.blocks[0].end), (0, instructions.len()));
+        assert_eq!(
+            cfg.to_dot(&instructions),
+            "digraph cfg {\n    node [shape=box, fontname=\"monospace\"];\n    \
+             B0 [label=\"B0\\l0: LIT 1\\l1: LIT 1\\l2: LOD 1\\l3: LOD 0\\l4: ADD\\l5: WRT\\l\"];\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_ssa_round_trip() {
+        let instructions = analyze(String::from("files/somhow.plc0"));
+        let ssa = SsaProgram::from_instructions(&instructions).unwrap();
+        ssa.verify().unwrap();
+        assert_eq!(
+            ssa.to_string(),
+            "b0:\n    %0 = const 1\n    store s0, %0\n    %2 = const 1\n    store s1, %2\n    \
+             %4 = add %2, %0\n    print %4\n"
+        );
+        // The declared slots stay where they are; constants are pushed again
+        // rather than loaded.
+        assert_eq!(
+            ssa.to_instructions().unwrap(),
+            vec![
+                Instruction(Operation::LIT, 1),
+                Instruction(Operation::LIT, 1),
+                Instruction(Operation::LIT, 1),
+                Instruction(Operation::LIT, 1),
+                Instruction(Operation::ADD, 0),
+                Instruction(Operation::WRT, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_common_subexpression_elimination() {
+        let instructions = analyze(String::from("files/cse.plc0"));
+        let optimized = optimize(&instructions);
+        let count_mul = |code: &[Instruction]| code.iter().filter(|instr| instr.0 == Operation::MUL).count();
+        assert_eq!(count_mul(&instructions), 3);
+        assert_eq!(count_mul(&optimized), 2);
+
+        let mut before = Vec::new();
+        let mut after = Vec::new();
+        run(instructions, &mut before).unwrap();
+        run(optimized, &mut after).unwrap();
+        assert_eq!(String::from_utf8(before).unwrap(), "17\n7\n-8\n");
+        assert_eq!(String::from_utf8(after).unwrap(), "17\n7\n-8\n");
+    }
+
+    #[test]
+    fn test_optimize_assembly() {
+        // The value stored is the one printed, not a declaration of its own.
+        let instructions = assemble("LIT 1\nLIT 2\nSTO 0\nWRT\n").unwrap();
+        let mut output = Vec::new();
+        run(optimize(&instructions), &mut output).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "2\n");
+    }
+
+    #[test]
+    fn test_optimize_keeps_slots() {
+        // c, a, b, x and y keep their slots; the shared a * b goes above them.
+        let optimized = optimize(&analyze(String::from("files/cse.plc0")));
+        let declarations = [5, 3, 4, 0, 0].iter().map(|&n| Instruction(Operation::LIT, n));
+        assert!(optimized.iter().take(5).cloned().eq(declarations));
+        assert!(optimized.contains(&Instruction(Operation::STO, 1)));
+        assert!(optimized.contains(&Instruction(Operation::STO, 5)));
+        let mut output = Vec::new();
+        run(optimized, &mut output).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "17\n7\n-8\n");
+    }
+
+    #[test]
+    fn test_optimize_keeps_failing_divisions() {
+        let optimized = optimize(&analyze(String::from("files/dead_division.plc0")));
+        let mut output = Vec::new();
+        let err = run(optimized, &mut output).unwrap_err();
+        assert_eq!(err.get_err_code(), &RuntimeErrorCode::ErrDivisionByZero);
+        assert_eq!(String::from_utf8(output).unwrap(), "1\n");
+
+        // The division is used once only, after the output.
+        let instructions = assemble("LIT 0\nLIT 7\nLOD 0\nDIV\nLIT 1\nWRT\nWRT\n").unwrap();
+        let mut output = Vec::new();
+        run(optimize(&instructions), &mut output).unwrap_err();
+        assert_eq!(String::from_utf8(output).unwrap(), "");
+    }
+
+    #[test]
+    fn test_bytecode_round_trip() {
+        let (bytecode, _) = compile(String::from("files/warnings.plc0")).unwrap();
+        let bytes = bytecode.to_bytes().unwrap();
+        assert_eq!(Bytecode::from_bytes(&bytes).unwrap(), bytecode);
+        assert_eq!(bytecode.slot_names.unwrap(), ["c", "k", "a", "b", "n"]);
+
+        let err = Bytecode::from_bytes(&bytes[..bytes.len() - 1]).unwrap_err();
+        assert_eq!(err.get_err_code(), &BytecodeErrorCode::ErrTruncated);
+        let mut corrupted = bytes.clone();
+        corrupted[20] = 42;
+        let err = Bytecode::from_bytes(&corrupted).unwrap_err();
+        assert_eq!((err.get_offset(), err.get_err_code()), (20, &BytecodeErrorCode::ErrInvalidOpcode));
+
+        // Slots popped again before the end still count.
+        let instructions = assemble("LIT 1\nLIT 2\nLOD 1\nWRT\nSTO 0\nLOD 0\nWRT\n").unwrap();
+        let bytecode = Bytecode::from_instructions(instructions);
+        assert_eq!(bytecode.slot_count, 2);
+        assert_eq!(Bytecode::from_bytes(&bytecode.to_bytes().unwrap()).unwrap(), bytecode);
+        let long_name = Bytecode { slot_names: Some(vec!["a".repeat(70000)]), ..Bytecode::from_instructions(vec![]) };
+        let err = long_name.to_bytes().unwrap_err();
+        assert_eq!(err.get_err_code(), &BytecodeErrorCode::ErrInvalidDebugInfo);
+    }
+
+    #[test]
+    fn test_assembler() {
+        let instructions = analyze(String::from("files/cse.plc0"));
+        let listing: String = instructions.iter().map(|instr| format!("{}\n", instr)).collect();
+        assert_eq!(assemble(&listing).unwrap(), instructions);
+
+        let source = "start: lit 7 ; seven\n# stored below\n  LIT start\nend:\nSTO 0\nLOD end\nWRT\n";
+        assert_eq!(
+            assemble(source).unwrap(),
+            vec![
+                Instruction(Operation::LIT, 7),
+                Instruction(Operation::LIT, 0),
+                Instruction(Operation::STO, 0),
+                Instruction(Operation::LOD, 2),
+                Instruction(Operation::WRT, 0),
+            ]
+        );
+        let err = assemble("LIT 1\nADD 2\n").unwrap_err();
+        assert_eq!((err.get_line(), err.get_err_code()), (2, &AssemblyErrorCode::ErrUnexpectedOperand));
+        let err = assemble("LIT 1\n\nLOD nowhere\n").unwrap_err();
+        assert_eq!((err.get_line(), err.get_err_code()), (3, &AssemblyErrorCode::ErrUndefinedLabel));
+    }
+
+    #[test]
+    fn test_annotated_listing() {
+        let (bytecode, _) = compile(String::from("files/somhow.plc0")).unwrap();
+        let source = std::fs::read_to_string("files/somhow.plc0").unwrap();
+        assert_eq!(
+            disassemble(&bytecode, Some(&source)),
+            "; 2: const b = 1;\n0  LIT 1\n; 3: var a = 1;\n1  LIT 1\n; 4: print(a+b);\n\
+             2  LOD 1      ; a\n3  LOD 0      ; b\n4  ADD\n5  WRT\n"
+        );
+    }
+
+    #[test]
+    fn test_runtime_error_position() {
+        let (bytecode, _) = compile(String::from("files/div_zero.plc0")).unwrap();
+        let line_table = bytecode.line_table.clone().unwrap();
+        assert_eq!(line_table.len(), bytecode.instructions.len());
+
+        let mut vm = VirtualMachine::new(bytecode.instructions.clone());
+        vm.set_line_table(line_table);
+        let err = vm.run(&mut Vec::new()).unwrap_err();
+        assert_eq!(err.to_string(), "division by zero at 4:19");
+        let err = run(bytecode.instructions, &mut Vec::new()).unwrap_err();
+        assert_eq!(err.to_string(), "division by zero at instruction 6");
+    }
+
+    #[test]
+    fn test_optimize_keeps_debug_info() {
+        let (bytecode, _) = compile(String::from("files/div_zero.plc0")).unwrap();
+        let optimized = optimize_bytecode(&bytecode);
+        let line_table = optimized.line_table.unwrap();
+        assert_eq!(line_table.len(), optimized.instructions.len());
+        let mut vm = VirtualMachine::new(optimized.instructions);
+        vm.set_line_table(line_table);
+        let err = vm.run(&mut Vec::new()).unwrap_err();
+        assert_eq!(err.to_string(), "division by zero at 4:19");
+
+        // The temporary holding a * b is named like an unnamed slot.
+        let (bytecode, _) = compile(String::from("files/cse.plc0")).unwrap();
+        let optimized = optimize_bytecode(&bytecode);
+        assert_eq!(optimized.const_count, 1);
+        assert_eq!(optimized.slot_names.unwrap(), ["c", "a", "b", "x", "y", "s_5"]);
+    }
+
+    #[test]
+    fn test_debugger() {
+        let (bytecode, _) = compile(String::from("files/warnings.plc0")).unwrap();
+        let mut debugger = Debugger::new(bytecode);
+        let mut run = |command: &str| {
+            let mut output = Vec::new();
+            assert!(debugger.execute(command, &mut output));
+            String::from_utf8(output).unwrap()
+        };
+        assert_eq!(run("break line 8"), "breakpoint at 7 (at 8:15)\n");
+        assert_eq!(run("watch b"), "");
+        assert_eq!(run("continue"), "watchpoint b: ? -> 0\n=>    4  LIT 0 (at 6:15)\n");
+        assert_eq!(run("c"), "watchpoint b: 0 -> 1\nbreakpoint at 7\n=>    7  LOD 2 ; a (at 8:15)\n");
+        assert_eq!(run("p k"), "const k = 3\n");
+        assert_eq!(run("next"), "=>    9  LOD 0 ; c (at 9:17)\n");
+        assert_eq!(run("step"), "=>   10  WRT (at 9:11)\n");
+        assert_eq!(run("stack"), "   5  2\n   4  0           ; n\n   3  1           ; b\n   2  1           ; a\n   1  3           ; k\n   0  2           ; c\n");
+        assert_eq!(run("c"), "2\nprogram finished\n");
+        assert_eq!(run("s"), "error: the program is not running\n");
+        assert_eq!(run("watch x"), "error: no variable 'x'\n");
+
+        // A breakpoint on the first instruction, and a change made by the last.
+        let mut debugger = Debugger::new(Bytecode::from_instructions(assemble("LIT 0\nLIT 1\nSTO 0\n").unwrap()));
+        let mut run = |command: &str| {
+            let mut output = Vec::new();
+            assert!(debugger.execute(command, &mut output));
+            String::from_utf8(output).unwrap()
+        };
+        assert_eq!(run("break 0"), "breakpoint at 0\n");
+        assert_eq!(run("c"), "breakpoint at 0\n=>    0  LIT 0\n");
+        assert_eq!(run("watch #0"), "");
+        assert_eq!(run("c"), "watchpoint #0: ? -> 0\n=>    1  LIT 1\n");
+        assert_eq!(run("c"), "watchpoint #0: 0 -> 1\nprogram finished\n");
+
+        // The stack holds the operands of a's initializer before a and b are
+        // declared, at the positions of their slots.
+        let (bytecode, _) = compile(String::from("files/compound.plc0")).unwrap();
+        let mut debugger = Debugger::new(bytecode);
+        let mut run = |command: &str| {
+            let mut output = Vec::new();
+            assert!(debugger.execute(command, &mut output));
+            String::from_utf8(output).unwrap()
+        };
+        assert_eq!(run("s"), "=>    1  LIT 2 (at 2:23)\n");
+        assert_eq!(run("s"), "=>    2  ADD (at 2:21)\n");
+        assert_eq!(run("stack"), "   1  2\n   0  1\n");
+        assert_eq!(run("p b"), "var b is not declared yet\n");
+        assert_eq!(run("watch b"), "");
+        assert_eq!(run("c"), "watchpoint b: ? -> 5\n=>    4  LOD 1 ; b (at 4:17)\n");
+    }
+
+    #[test]
+    fn test_trace() {
+        let instructions = analyze(String::from("files/somhow.plc0"));
+        let mut text = Vec::new();
+        VirtualMachine::new(instructions.clone())
+            .run_with_trace(&mut Vec::new(), &mut text, TraceFormat::Text)
+            .unwrap();
+        let text = String::from_utf8(text).unwrap();
+        assert_eq!(text.lines().count(), instructions.len());
+        assert_eq!(text.lines().nth(4).unwrap(), "    4  ADD        [1, 1, 2]");
+
+        let mut json = Vec::new();
+        VirtualMachine::new(instructions)
+            .run_with_trace(&mut Vec::new(), &mut json, TraceFormat::JsonLines)
+            .unwrap();
+        let json = String::from_utf8(json).unwrap();
+        assert_eq!(json.lines().next().unwrap(), r#"{"pc":0,"op":"LIT","operand":1,"stack":[1]}"#);
+        assert_eq!(json.lines().last().unwrap(), r#"{"pc":5,"op":"WRT","operand":null,"stack":[1,1]}"#);
+    }
+
+    #[test]
+    fn test_limits() {
+        let instructions = analyze(String::from("files/cse.plc0"));
+        let run_limited = |limits: Limits| {
+            let mut vm = VirtualMachine::new(instructions.clone());
+            vm.set_limits(limits);
+            let mut output = Vec::new();
+            vm.run(&mut output).map(|_| String::from_utf8(output).unwrap())
+        };
+        let limits = Limits { max_instructions: Some(5), ..Limits::default() };
+        assert_eq!(run_limited(limits).unwrap_err().get_err_code(), &RuntimeErrorCode::ErrInstructionLimit);
+        let limits = Limits { max_stack: Some(5), ..Limits::default() };
+        assert_eq!(run_limited(limits).unwrap_err().get_err_code(), &RuntimeErrorCode::ErrStackLimit);
+        let limits = Limits { max_output: Some(4), ..Limits::default() };
+        assert_eq!(run_limited(limits).unwrap_err().get_err_code(), &RuntimeErrorCode::ErrOutputLimit);
+        let limits = Limits {
+            max_instructions: Some(instructions.len() as u64),
+            max_stack: Some(7),
+            max_output: Some(8),
+        };
+        assert_eq!(run_limited(limits).unwrap(), "17\n7\n-8\n");
+    }
+
+    #[test]
+    fn test_repl() {
+        let mut repl = Repl::new();
+        let mut eval = |input: &str| {
+            let mut output = Vec::new();
+            assert!(repl.eval(input, &mut output));
+            String::from_utf8(output).unwrap()
+        };
+        assert_eq!(eval("const c = 2;"), "");
+        assert_eq!(eval("var a = c * 3; print(a + 1);"), "7\n");
+        assert_eq!(eval("print(a / (c - 2));"), "runtime error: division by zero at 1:9\n");
+        assert_eq!(eval("var b; print(b);"), "error: variable used before it is assigned at 1:15\n");
+        assert_eq!(eval("var b; b = a - 1;"), "");
+        assert_eq!(eval(":vars"), "const c = 2\nvar a = 6\nvar b = 5\n");
+        assert_eq!(eval(":code").lines().count(), 13);
+        assert_eq!(eval(":tokens b = 1"), "Identifier b\nEqualSign =\nUnsignedInteger 1\n");
+        assert_eq!(eval("c = 1;"), "error: cannot assign to a constant at 1:2\n");
+    }
+
+    #[test]
+    fn test_c_backend() {
+        let (bytecode, _) = compile(String::from("files/cse.plc0")).unwrap();
+        let source = emit_c(&bytecode).unwrap();
+        assert!(source.contains("    x = plc0_add(plc0_mul(a, b), c);\n"));
+        assert!(source.contains("    printf(\"%ld\\n\", (long)plc0_mul(a, b));\n"));
+        let err = emit_c(&Bytecode::from_instructions(vec![Instruction(Operation::ADD, 0)])).unwrap_err();
+        assert_eq!(err.get_err_code(), &CodegenErrorCode::ErrStackUnderflow);
+
+        // Names the headers define.
+        let (bytecode, _) = compile(String::from("files/reserved_names.plc0")).unwrap();
+        let source = emit_c(&bytecode).unwrap();
+        assert!(source.contains("    int32_t EOF_ = 1;\n    int32_t NULL_ = 2;\n    int32_t bool_ = 3;\n"));
+    }
+
+    #[test]
+    #[ignore = "needs a C compiler"]
+    fn test_c_backend_run() {
+        run_backend("c", |dir, name, bytecode| {
+            std::fs::write(dir.join(format!("{}.c", name)), emit_c(bytecode).unwrap()).unwrap();
+            build_step(Command::new("cc").arg("-o").arg(dir.join(name)).arg(dir.join(format!("{}.c", name))));
+            Command::new(dir.join(name))
+        });
+
+        let dir = std::env::temp_dir().join("rminiplc0_test_c_backend");
+        let (bytecode, _) = compile(String::from("files/reserved_names.plc0")).unwrap();
+        std::fs::write(dir.join("reserved_names.c"), emit_c(&bytecode).unwrap()).unwrap();
+        let exe = dir.join("reserved_names");
+        build_step(Command::new("cc").arg("-std=c2x").arg("-o").arg(&exe).arg(dir.join("reserved_names.c")));
+        assert_eq!(Command::new(exe).output().unwrap().stdout, b"15\n");
+    }
+
+    #[test]
+    fn test_x86_64_backend() {
+        let (bytecode, _) = compile(String::from("files/div_zero.plc0")).unwrap();
+        let source = emit_x86_64(&bytecode).unwrap();
+        assert!(source.contains("    # LOD 0 ; a\n    movl -4(%rbp), %eax\n    movl %eax, -12(%rbp)\n"));
+        assert!(source.contains("    .ascii \"runtime error: division by zero at 4:19\\n\"\n"));
+    }
+
+    #[test]
+    #[ignore = "needs binutils"]
+    #[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+    fn test_x86_64_backend_run() {
+        run_backend("x86_64", |dir, name, bytecode| {
+            std::fs::write(dir.join(format!("{}.s", name)), emit_x86_64(bytecode).unwrap()).unwrap();
+            let object = dir.join(format!("{}.o", name));
+            build_step(Command::new("as").arg("-o").arg(&object).arg(dir.join(format!("{}.s", name))));
+            build_step(Command::new("ld").arg("-o").arg(dir.join(name)).arg(&object));
+            Command::new(dir.join(name))
+        });
+    }
+
+    #[test]
+    fn test_riscv_backend() {
+        let (bytecode, _) = compile(String::from("files/cse.plc0")).unwrap();
+        let rv32 = emit_riscv(&bytecode, RiscvTarget::Rv32).unwrap();
+        let rv64 = emit_riscv(&bytecode, RiscvTarget::Rv64).unwrap();
+        assert!(rv32.contains("    # MUL\n    mul t0, t0, t1\n"));
+        assert!(rv64.contains("    # MUL\n    mulw t0, t0, t1\n"));
+        assert!(rv32.contains("    mv a0, t0\n    li a7, 1\n    ecall\n"));
+
+        // Nine pushes before the first ADD need more than the 7 registers.
+        let mut instructions: Vec<_> = (1..=9).map(|value| Instruction(Operation::LIT, value)).collect();
+        instructions.extend(vec![Instruction(Operation::ADD, 0); 8]);
+        instructions.push(Instruction(Operation::WRT, 0));
+        let spilled = emit_riscv(&Bytecode::from_instructions(instructions), RiscvTarget::Rv32).unwrap();
+        assert!(spilled.contains("    # LIT 8\n    sw t0, 0(sp)\n    li t0, 8\n"));
+        assert!(spilled.contains("    # LIT 9\n    sw t1, 4(sp)\n    li t1, 9\n"));
+    }
+
+    #[test]
+    fn test_wat_backend() {
+        let (bytecode, _) = compile(String::from("files/div_zero.plc0")).unwrap();
+        let module = emit_wat(&bytecode).unwrap();
+        assert!(module.starts_with("(module\n  (import \"env\" \"print\" (func $print (param i32)))\n"));
+        assert!(module.contains("  (func (export \"main\")\n    (local $a i32)\n    (local $b i32)\n    (local $t_2 i32)\n"));
+        assert!(module.contains("  (data (i32.const 0) \"runtime error: division by zero at 4:19\")\n"));
+        assert!(module.contains("    local.get $b\n    i32.const 0\n    i32.const 39\n    call $div ;; 4:19\n    local.set $t_2\n"));
+        wasmparser::validate(&wat::parse_str(&module).unwrap()).unwrap();
+
+        // The value loaded before the assignment is the old one.
+        let listing = "LIT 1\nLOD 0\nLIT 5\nSTO 0\nLOD 0\nADD\nWRT\n";
+        let module = emit_wat(&Bytecode::from_instructions(assemble(listing).unwrap())).unwrap();
+        assert!(module.contains("    local.get $s_0\n    local.set $t_1\n    i32.const 5\n    local.set $s_0\n"));
+        wasmparser::validate(&wat::parse_str(&module).unwrap()).unwrap();
+    }
+
+    #[test]
+    #[ignore = "needs Node.js"]
+    fn test_wat_backend_run() {
+        // The host a module is run with, reporting errors like the JavaScript
+        // backend's scripts.
+        const HOST: &str = "const bytes = require('fs').readFileSync(process.argv[1]); let memory;
+const env = { print: (value) => console.log(value), error: (offset, length) => {
+  throw new Error(Buffer.from(memory.buffer, offset, length).toString()); } };
+WebAssembly.instantiate(bytes, { env }).then(({ instance }) => {
+  memory = instance.exports.memory; instance.exports.main();
+}).catch((err) => { console.error(err.message); process.exitCode = 1; });";
+        run_backend("wat", |dir, name, bytecode| {
+            let binary = wat::parse_str(emit_wat(bytecode).unwrap()).unwrap();
+            std::fs::write(dir.join(format!("{}.wasm", name)), binary).unwrap();
+            let mut command = Command::new("node");
+            command.arg("-e").arg(HOST).arg(dir.join(format!("{}.wasm", name)));
+            command
+        });
+    }
+
+    #[test]
+    fn test_llvm_backend() {
+        let (bytecode, _) = compile(String::from("files/cse.plc0")).unwrap();
+        let module = emit_llvm(&bytecode).unwrap();
+        assert!(module.contains("define i32 @main() {\nentry:\n  %c = alloca i32\n  %a = alloca i32\n"));
+        assert!(module.contains("  %v.2 = mul i32 %v.0, %v.1\n"));
+        assert!(module.contains("declare i32 @printf(i8*, ...)\n"));
+    }
+
+    #[test]
+    #[ignore = "needs LLVM's interpreter lli"]
+    fn test_llvm_backend_run() {
+        run_backend("llvm", |dir, name, bytecode| {
+            std::fs::write(dir.join(format!("{}.ll", name)), emit_llvm(bytecode).unwrap()).unwrap();
+            let mut command = Command::new("lli");
+            command.arg(dir.join(format!("{}.ll", name)));
+            command
+        });
+    }
+
+    #[test]
+    fn test_jvm_backend() {
+        let (bytecode, _) = compile(String::from("files/div_zero.plc0")).unwrap();
+        let class = emit_jvm(&bytecode, "DivZero").unwrap();
+        assert_eq!(class[..8], [0xca, 0xfe, 0xba, 0xbe, 0, 0, 0, 52]);
+        let contains = |text: &[u8]| class.windows(text.len()).any(|window| window == text);
+        assert!(contains(b"StackMapTable"));
+        assert!(contains(b"4:19"));
+
+        // Locals above 255 are addressed with `wide`, and methods are limited
+        // to 64 KiB of code.
+        let listing: String = (0..300).map(|slot| format!("LIT {}\n", slot)).collect();
+        let listing = listing + &(0..300).map(|slot| format!("LOD {}\nWRT\n", slot)).collect::<String>();
+        let class = emit_jvm(&Bytecode::from_instructions(assemble(&listing).unwrap()), "Locals").unwrap();
+        assert!(class.windows(4).any(|window| window == [0xc4, 0x15, 0x01, 0x2b]));
+        let listing = "LIT 100000\nWRT\n".repeat(20000);
+        let err = emit_jvm(&Bytecode::from_instructions(assemble(&listing).unwrap()), "Long").unwrap_err();
+        assert_eq!(err.get_err_code(), &CodegenErrorCode::ErrTooLarge);
+    }
+
+    #[test]
+    #[ignore = "needs a JDK"]
+    fn test_jvm_backend_run() {
+        run_backend("jvm", |dir, name, bytecode| {
+            std::fs::write(dir.join(format!("{}.class", name)), emit_jvm(bytecode, name).unwrap()).unwrap();
+            let mut command = Command::new("java");
+            command.arg("-cp").arg(dir).arg(name);
+            command
+        });
+
+        let dir = std::env::temp_dir().join("rminiplc0_test_jvm_backend");
+        let listing: String = (0..300).map(|slot| format!("LIT {}\n", slot)).collect();
+        let listing = listing + &(0..300).map(|slot| format!("LOD {}\nWRT\n", slot)).collect::<String>();
+        let class = emit_jvm(&Bytecode::from_instructions(assemble(&listing).unwrap()), "Locals").unwrap();
+        std::fs::write(dir.join("Locals.class"), class).unwrap();
+        let output = Command::new("java").arg("-cp").arg(&dir).arg("Locals").output().unwrap();
+        let expected: String = (0..300).map(|slot| format!("{}\n", slot)).collect();
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_js_backend() {
+        let (bytecode, _) = compile(String::from("files/cse.plc0")).unwrap();
+        let script = emit_js(&bytecode).unwrap();
+        assert!(script.contains("  x = (Math.imul(a, b) + c | 0);\n"));
+        assert!(script.contains("function plc0_run(print = (value) => console.log(value)) {\n  let c = 5;\n"));
+    }
+
+    #[test]
+    #[ignore = "needs Node.js"]
+    fn test_js_backend_run() {
+        run_backend("js", |dir, name, bytecode| {
+            std::fs::write(dir.join(format!("{}.js", name)), emit_js(bytecode).unwrap()).unwrap();
+            let mut command = Command::new("node");
+            command.arg(dir.join(format!("{}.js", name)));
+            command
+        });
+
+        // Collecting the output through the callback instead.
+        let script = std::env::temp_dir().join("rminiplc0_test_js_backend").join("cse.js");
+        let collect = "const out = []; require(process.argv[1])((value) => out.push(value)); console.log(out.join(','));";
+        let output = Command::new("node").arg("-e").arg(collect).arg(script).output().unwrap();
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "17,7,-8\n");
+    }
+
+    #[test]
+    fn test_register_vm() {
+        let instructions = analyze(String::from("files/cse.plc0"));
+        let program = lower(&instructions).unwrap();
+        let listing: Vec<String> = program.instructions.iter().map(|instr| instr.to_string()).collect();
+        assert_eq!(listing[5..8], ["mul r5, r1, r2", "add r5, r5, r0", "mov r3, r5"]);
+        assert!(program.instructions.len() < instructions.len());
+        let mut output = Vec::new();
+        RegisterMachine::new(program).run(&mut output).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "17\n7\n-8\n");
+
+        // A pending LOD reads the value from before the STO.
+        let program = lower(&assemble("LIT 1\nLOD 0\nLIT 5\nSTO 0\nLOD 0\nADD\nWRT\n").unwrap()).unwrap();
+        let mut output = Vec::new();
+        RegisterMachine::new(program).run(&mut output).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "6\n");
+
+        let (bytecode, _) = compile(String::from("files/div_zero.plc0")).unwrap();
+        let mut machine = RegisterMachine::new(lower(&bytecode.instructions).unwrap());
+        machine.set_line_table(bytecode.line_table.unwrap());
+        let err = machine.run(&mut Vec::new()).unwrap_err();
+        assert_eq!(err.to_string(), "division by zero at 4:19");
+    }
+
+    #[test]
+    fn test_comments() {
+        let source = "begin // start\nvar a = 1; /* one\n two */\nprint(a / 2);\nend";
+        let (tokens, comments) = tokenize_with_comments(source).unwrap();
+        let tokens: Vec<String> = tokens.iter().map(|token| token.to_string()).collect();
+        assert_eq!(tokens.join(" "), "begin var a = 1 ; print ( a / 2 ) ; end");
+        assert_eq!(comments[0].text, "// start");
+        assert_eq!(comments[1].text, "/* one\n two */");
+        assert_eq!((comments[1].span.start, comments[1].span.end), ((1, 11), (2, 7)));
+        let err = tokenize_source("begin /* open").unwrap_err();
+        assert_eq!((err.get_pos(), err.get_err_code()), ((0, 6), &ErrorCode::ErrIncompleteComment));
+        let err = tokenize_source("begin\n  var a = #;").unwrap_err();
+        assert_eq!((err.get_pos(), err.get_err_code()), ((1, 10), &ErrorCode::ErrInvalidInput));
+    }
+
+    #[test]
+    fn test_formatter() {
+        let source = "  begin\nconst b=-1 ; // base\n var a = 1;var c;\n\n\n  // compute\nc = a*(b+ - 2)/ -a;\n  \
+                      a = a + // carried\nc;\nprint(a+b);\n        end;";
+        let formatted = format_source(source).unwrap();
+        assert_eq!(
+            formatted,
+            "begin\n    const b = -1;  // base\n    var a = 1;\n    var c;\n\n    // compute\n    \
+             c = a * (b + -2) / -a;\n    a = a +  // carried\n        c;\n    print(a + b);\nend;\n"
+        );
+        assert_eq!(format_source(&formatted).unwrap(), formatted);
+
+        // Only the layout changes.
+        for file in ["files/somhow.plc0", "files/cse.plc0", "files/warnings.plc0"] {
+            let source = std::fs::read_to_string(file).unwrap();
+            let mut original = Analyzer::new(tokenize_source(&source).unwrap());
+            let mut formatted = Analyzer::new(tokenize_source(&format_source(&source).unwrap()).unwrap());
+            assert_eq!(original.analyze().unwrap(), formatted.analyze().unwrap());
+        }
+    }
+
+    #[test]
+    fn test_syntax_tree() {
+        let sources = [
+            std::fs::read_to_string("files/somhow.plc0").unwrap(),
+            String::from("begin\r\n  var a = -(1);  // one\r\n\tprint(a * 2 + a);\nend"),
+            String::from("x begin const = ; print(( ; end ; é /* open"),
+            String::new(),
+        ];
+        for source in &sources {
+            assert_eq!(parse(source).to_string(), *source);
+        }
+
+        let tree = parse(&sources[1]);
+        let kinds: Vec<NodeKind> = tree.root.nodes().map(|node| node.kind).collect();
+        assert_eq!(kinds, [NodeKind::VarDeclaration, NodeKind::PrintStatement]);
+        let tokens = tree.root.tokens();
+        let semicolon = tokens[8];
+        assert_eq!((semicolon.text.as_str(), semicolon.span.start), (";", (1, 14)));
+        let trailing: Vec<TriviaKind> = semicolon.trailing.iter().map(|trivia| trivia.kind).collect();
+        assert_eq!(trailing, [TriviaKind::Whitespace, TriviaKind::LineComment]);
+        let leading: Vec<&str> = tokens[9].leading.iter().map(|trivia| trivia.text.as_str()).collect();
+        assert_eq!(leading, ["\r\n", "\t"]);
+        // a * 2 + a groups as (a * 2) + a.
+        let print = tree.root.nodes().nth(1).unwrap();
+        let sum = print.nodes().next().unwrap();
+        assert_eq!(sum.kind, NodeKind::BinaryExpression);
+        assert_eq!(sum.nodes().next().unwrap().to_string(), "a * 2 ");
+        assert!(matches!(&sum.children[1], SyntaxElement::Token(token) if token.text == "+"));
+
+        let kinds: Vec<NodeKind> = parse(&sources[2]).root.nodes().map(|node| node.kind).collect();
+        assert_eq!(kinds[..3], [NodeKind::Error, NodeKind::ConstDeclaration, NodeKind::PrintStatement]);
+    }
+
+    #[test]
+    fn test_language_server() {
+        let mut server = LanguageServer::new();
+        let request = |id: usize, method: &str, params: &str| {
+            format!(r#"{{"jsonrpc":"2.0","id":{},"method":"{}","params":{}}}"#, id, method, params)
+        };
+        let reply = |server: &mut LanguageServer, message: &str| {
+            let replies = server.handle(message);
+            assert_eq!(replies.len(), 1);
+            Json::parse(&replies[0]).unwrap()
+        };
+        let document = r#"{"textDocument":{"uri":"file:///a.plc0"}}"#;
+        let at = |line: usize, character: usize| {
+            format!(r#"{},"position":{{"line":{},"character":{}}}}}"#, &document[..document.len() - 1], line, character)
+        };
+
+        let early = reply(&mut server, &request(1, "textDocument/hover", &at(0, 0)));
+        assert_eq!(early.at(&["error", "code"]), Some(&Json::Number(-32002.0)));
+        let init = reply(&mut server, &request(2, "initialize", "{}"));
+        assert_eq!(init.at(&["result", "capabilities", "hoverProvider"]), Some(&Json::Bool(true)));
+        assert!(server.handle(r#"{"jsonrpc":"2.0","method":"initialized","params":{}}"#).is_empty());
+
+        // The error of the analyzer, then a warning once it is fixed.
+        let open = r#"{"jsonrpc":"2.0","method":"textDocument/didOpen","params":{"textDocument":{"uri":"file:///a.plc0","languageId":"plc0","version":1,"text":"begin\n  var ä = 1;\n  print(b);\nend"}}}"#;
+        let diagnostics = reply(&mut server, open);
+        let diagnostic = &diagnostics.at(&["params", "diagnostics"]).unwrap().as_array().unwrap()[0];
+        assert_eq!(diagnostic.get("message").and_then(Json::as_str), Some("invalid character"));
+        assert_eq!(diagnostic.at(&["range", "start", "character"]).and_then(Json::as_usize), Some(6));
+        let source = "begin\n  const b = - 1;\n  var a = b;\n  var c;\n  a = 2 * b;\n  print(a);\nend";
+        let change = format!(
+            r#"{{"jsonrpc":"2.0","method":"textDocument/didChange","params":{{"textDocument":{{"uri":"file:///a.plc0","version":2}},"contentChanges":[{}]}}}}"#,
+            Json::object(vec![("text", Json::string(source))])
+        );
+        let diagnostics = reply(&mut server, &change);
+        let diagnostic = &diagnostics.at(&["params", "diagnostics"]).unwrap().as_array().unwrap()[0];
+        assert_eq!(diagnostic.get("severity").and_then(Json::as_usize), Some(2));
+        assert_eq!(diagnostic.get("message").and_then(Json::as_str), Some("`c`: variable is never assigned [-Wnever-assigned]"));
+
+        let definition = reply(&mut server, &request(3, "textDocument/definition", &at(4, 11)));
+        assert_eq!(definition.at(&["result", "range", "start"]), Some(&Json::object(vec![("line", Json::number(1)), ("character", Json::number(8))])));
+        let references = reply(&mut server, &request(4, "textDocument/references", &at(2, 10)));
+        assert_eq!(references.get("result").and_then(Json::as_array).map(Vec::len), Some(3));
+        let without = format!(r#"{},"context":{{"includeDeclaration":false}}}}"#, &at(2, 10)[..at(2, 10).len() - 1]);
+        let references = reply(&mut server, &request(5, "textDocument/references", &without));
+        assert_eq!(references.get("result").and_then(Json::as_array).map(Vec::len), Some(2));
+        let hover = reply(&mut server, &request(6, "textDocument/hover", &at(2, 10)));
+        assert_eq!(hover.at(&["result", "contents", "value"]).and_then(Json::as_str), Some("```plc0\nconst b = -1\n```"));
+        let hover = reply(&mut server, &request(7, "textDocument/hover", &at(0, 2)));
+        assert_eq!(hover.get("result"), Some(&Json::Null));
+
+        let symbols = reply(&mut server, &request(8, "textDocument/documentSymbol", document));
+        let symbols = symbols.get("result").and_then(Json::as_array).unwrap();
+        let names: Vec<(&str, usize)> = symbols
+            .iter()
+            .map(|symbol| (symbol.get("name").and_then(Json::as_str).unwrap(), symbol.get("kind").and_then(Json::as_usize).unwrap()))
+            .collect();
+        assert_eq!(names, [("b", 14), ("a", 13), ("c", 13)]);
+        assert_eq!(symbols[0].at(&["range", "end", "character"]).and_then(Json::as_usize), Some(16));
+
+        let edits = reply(&mut server, &request(9, "textDocument/formatting", document));
+        let edit = &edits.get("result").and_then(Json::as_array).unwrap()[0];
+        assert_eq!(edit.get("newText").and_then(Json::as_str), Some(format_source(source).unwrap().as_str()));
+        assert_eq!(edit.at(&["range", "end"]), Some(&Json::object(vec![("line", Json::number(6)), ("character", Json::number(3))])));
+
+        let unknown = reply(&mut server, &request(10, "workspace/symbol", "{}"));
+        assert_eq!(unknown.at(&["error", "code"]), Some(&Json::Number(-32601.0)));
+        let invalid = reply(&mut server, "{\"id\":");
+        assert_eq!(invalid.get("id"), Some(&Json::Null));
+        reply(&mut server, &request(11, "shutdown", "null"));
+        server.handle(r#"{"jsonrpc":"2.0","method":"exit"}"#);
+        assert_eq!(server.exit_code(), Some(0));
+    }
+
+    // Builds files/cse.plc0 and files/div_zero.plc0 with a backend into a
+    // directory of its own and runs both, which must print what the VM does.
+    // `build` writes a program and returns the command running it. The tests
+    // calling this need tools which may not be installed, so they are ignored
+    // unless asked for with `cargo test -- --ignored`.
+    fn run_backend(backend: &str, build: impl Fn(&Path, &str, &Bytecode) -> Command) {
+        let dir = std::env::temp_dir().join(format!("rminiplc0_test_{}_backend", backend));
+        std::fs::create_dir_all(&dir).unwrap();
+        let expected = [
+            ("cse", Some(0), "17\n7\n-8\n", ""),
+            ("div_zero", Some(1), "", "runtime error: division by zero at 4:19\n"),
+        ];
+        for (name, code, stdout, stderr) in expected {
+            let (bytecode, _) = compile(format!("files/{}.plc0", name)).unwrap();
+            let mut command = build(&dir, name, &bytecode);
+            let output = command.output().unwrap_or_else(|err| panic!("cannot run {:?}: {}", command, err));
+            assert_eq!(
+                (output.status.code(), String::from_utf8(output.stdout).unwrap(), String::from_utf8(output.stderr).unwrap()),
+                (code, String::from(stdout), String::from(stderr)),
+                "{} built by the {} backend",
+                name,
+                backend
+            );
+        }
+    }
+
+    // A step of building a program, which has to succeed.
+    fn build_step(command: &mut Command) {
+        let status = command.status().unwrap_or_else(|err| panic!("cannot run {:?}: {}", command, err));
+        assert!(status.success(), "{:?} failed", command);
+    }
+}
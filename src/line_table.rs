@@ -0,0 +1,48 @@
+use std::fmt;
+
+// Source range of the token an instruction was generated from. Lines and
+// columns start from 0 like the tokenizer's, `end` is exclusive.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Span {
+    pub start: (usize, usize),
+    pub end: (usize, usize),
+}
+
+// One span per instruction, kept next to the instruction vector.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LineTable {
+    spans: Vec<Span>,
+}
+
+impl LineTable {
+    pub fn new() -> LineTable {
+        LineTable { spans: Vec::new() }
+    }
+
+    pub fn push(&mut self, span: Span) {
+        self.spans.push(span);
+    }
+
+    pub fn get(&self, index: usize) -> Option<&Span> {
+        self.spans.get(index)
+    }
+
+    pub fn len(&self) -> usize {
+        self.spans.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.spans.is_empty()
+    }
+
+    pub fn spans(&self) -> &Vec<Span> {
+        &self.spans
+    }
+}
+
+// 1-based "line:column" of the start, the way editors count.
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.start.0 + 1, self.start.1 + 1)
+    }
+}
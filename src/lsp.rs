@@ -0,0 +1,389 @@
+use crate::{
+    analyzer::Analyzer,
+    formatter::format_source,
+    json::Json,
+    line_table::Span,
+    syntax::{parse, NodeKind, SyntaxElement, SyntaxNode, SyntaxToken, SyntaxTree},
+    tokenizer::{tokenize_source, TokenType},
+};
+use std::collections::HashMap;
+
+const PARSE_ERROR: i32 = -32700;
+const INVALID_REQUEST: i32 = -32600;
+const METHOD_NOT_FOUND: i32 = -32601;
+const SERVER_NOT_INITIALIZED: i32 = -32002;
+
+// LSP's SymbolKind and DiagnosticSeverity.
+const SYMBOL_VARIABLE: usize = 13;
+const SYMBOL_CONSTANT: usize = 14;
+const SEVERITY_ERROR: usize = 1;
+const SEVERITY_WARNING: usize = 2;
+
+// A `const` or `var` declaration found in the syntax tree.
+struct Declaration<'a> {
+    node: &'a SyntaxNode,
+    name: &'a SyntaxToken,
+}
+
+// The Language Server Protocol for miniplc0 files, with the whole text sent on
+// every change. `handle` takes the JSON of one message and returns the
+// messages to send back; the framing with Content-Length headers is left to
+// the caller. Programs only have one scope, so a name always refers to its
+// one declaration.
+//
+// Positions are exchanged in UTF-16 code units, as the protocol wants, and
+// converted to and from the byte columns of the tokenizer.
+#[derive(Default)]
+pub struct LanguageServer {
+    documents: HashMap<String, String>,
+    initialized: bool,
+    shutdown: bool,
+    exit_code: Option<i32>,
+}
+
+impl LanguageServer {
+    pub fn new() -> LanguageServer {
+        LanguageServer::default()
+    }
+
+    // Set once the client sent `exit`: 0 after a `shutdown` request, 1 without.
+    pub fn exit_code(&self) -> Option<i32> {
+        self.exit_code
+    }
+
+    pub fn handle(&mut self, message: &str) -> Vec<String> {
+        let message = match Json::parse(message) {
+            Ok(message) => message,
+            Err(err) => return vec![error_response(&Json::Null, PARSE_ERROR, &err)],
+        };
+        let method = match message.get("method").and_then(Json::as_str) {
+            Some(method) => method,
+            // Responses to requests of ours, which this server never sends.
+            None => return Vec::new(),
+        };
+        let params = message.get("params").unwrap_or(&Json::Null);
+        let id = match message.get("id") {
+            Some(id) => id,
+            None => return self.notification(method, params),
+        };
+        if !self.initialized && method != "initialize" {
+            return vec![error_response(id, SERVER_NOT_INITIALIZED, "the server is not initialized yet")];
+        }
+        if self.shutdown {
+            return vec![error_response(id, INVALID_REQUEST, "the server is shutting down")];
+        }
+        let result = match method {
+            "initialize" => {
+                self.initialized = true;
+                initialize_result()
+            }
+            "shutdown" => {
+                self.shutdown = true;
+                Json::Null
+            }
+            "textDocument/definition" => self.with_document(params, definition),
+            "textDocument/references" => self.with_document(params, references),
+            "textDocument/hover" => self.with_document(params, hover),
+            "textDocument/documentSymbol" => self.with_document(params, document_symbols),
+            "textDocument/formatting" => self.with_document(params, formatting),
+            _ => return vec![error_response(id, METHOD_NOT_FOUND, &format!("unknown method '{}'", method))],
+        };
+        vec![Json::object(vec![("jsonrpc", Json::string("2.0")), ("id", id.clone()), ("result", result)]).to_string()]
+    }
+
+    fn notification(&mut self, method: &str, params: &Json) -> Vec<String> {
+        let uri = params.at(&["textDocument", "uri"]).and_then(Json::as_str).map(String::from);
+        match (method, uri) {
+            ("exit", _) => {
+                self.exit_code = Some(if self.shutdown { 0 } else { 1 });
+                Vec::new()
+            }
+            ("textDocument/didOpen", Some(uri)) => {
+                let text = params.at(&["textDocument", "text"]).and_then(Json::as_str).unwrap_or("");
+                self.documents.insert(uri.clone(), String::from(text));
+                vec![self.diagnostics(&uri)]
+            }
+            ("textDocument/didChange", Some(uri)) => {
+                let changes = params.get("contentChanges").and_then(Json::as_array);
+                let text = changes.and_then(|changes| changes.last()).and_then(|change| change.get("text"));
+                match text.and_then(Json::as_str) {
+                    Some(text) => {
+                        self.documents.insert(uri.clone(), String::from(text));
+                        vec![self.diagnostics(&uri)]
+                    }
+                    None => Vec::new(),
+                }
+            }
+            ("textDocument/didClose", Some(uri)) => {
+                self.documents.remove(&uri);
+                vec![publish_diagnostics(&uri, Vec::new())]
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    // Runs a request about a position or a whole document on its syntax tree;
+    // null for documents which aren't open.
+    fn with_document(&self, params: &Json, request: fn(&Document, &Json) -> Json) -> Json {
+        let uri = params.at(&["textDocument", "uri"]).and_then(Json::as_str).unwrap_or("");
+        match self.documents.get(uri) {
+            Some(text) => request(&Document { uri, text, tree: parse(text) }, params),
+            None => Json::Null,
+        }
+    }
+
+    // The errors of the analyzer, or its warnings for programs that compile.
+    fn diagnostics(&self, uri: &str) -> String {
+        let text = &self.documents[uri];
+        let document = Document { uri, text, tree: parse(text) };
+        let diagnostic = |pos: (usize, usize), severity: usize, message: String| {
+            Json::object(vec![
+                ("range", document.range_at(pos)),
+                ("severity", Json::number(severity)),
+                ("source", Json::string("plc0")),
+                ("message", Json::String(message)),
+            ])
+        };
+        let analyzed = tokenize_source(text).and_then(|tokens| {
+            let mut analyzer = Analyzer::new(tokens);
+            analyzer.analyze()?;
+            Ok(analyzer.take_warnings())
+        });
+        let diagnostics = match analyzed {
+            Ok(warnings) => warnings
+                .iter()
+                .map(|warning| diagnostic(warning.get_pos(), SEVERITY_WARNING, warning.message()))
+                .collect(),
+            Err(err) => vec![diagnostic(err.get_pos(), SEVERITY_ERROR, err.get_err_code().to_string())],
+        };
+        publish_diagnostics(uri, diagnostics)
+    }
+}
+
+struct Document<'a> {
+    uri: &'a str,
+    text: &'a str,
+    tree: SyntaxTree,
+}
+
+impl<'a> Document<'a> {
+    fn declarations(&self) -> Vec<Declaration<'_>> {
+        self.tree
+            .root
+            .nodes()
+            .filter(|node| matches!(node.kind, NodeKind::ConstDeclaration | NodeKind::VarDeclaration))
+            .filter_map(|node| match node.children.get(1) {
+                Some(SyntaxElement::Token(name)) if name.kind == TokenType::Identifier => Some(Declaration { node, name }),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn identifiers(&self) -> Vec<&SyntaxToken> {
+        self.tree.root.tokens().into_iter().filter(|token| token.kind == TokenType::Identifier).collect()
+    }
+
+    // The identifier at the request's position, the cursor touching either end.
+    fn identifier_at(&self, params: &Json) -> Option<&SyntaxToken> {
+        let line = params.at(&["position", "line"]).and_then(Json::as_usize)?;
+        let character = params.at(&["position", "character"]).and_then(Json::as_usize)?;
+        let col = self.byte_col(line, character);
+        self.identifiers()
+            .into_iter()
+            .find(|token| token.span.start.0 == line && (token.span.start.1..=token.span.end.1).contains(&col))
+    }
+
+    // The first declaration of the name, later ones being errors.
+    fn declaration_of(&self, name: &str) -> Option<Declaration<'_>> {
+        self.declarations().into_iter().find(|declaration| declaration.name.text == name)
+    }
+
+    fn line(&self, line: usize) -> &str {
+        self.text.split('\n').nth(line).unwrap_or("")
+    }
+
+    fn byte_col(&self, line: usize, character: usize) -> usize {
+        let text = self.line(line);
+        let mut units = 0;
+        for (col, ch) in text.char_indices() {
+            if units >= character {
+                return col;
+            }
+            units += ch.len_utf16();
+        }
+        text.len()
+    }
+
+    fn position(&self, (line, col): (usize, usize)) -> Json {
+        let text = self.line(line);
+        let character = text.get(..col).map_or(col, |prefix| prefix.encode_utf16().count());
+        Json::object(vec![("line", Json::number(line)), ("character", Json::number(character))])
+    }
+
+    fn range(&self, span: Span) -> Json {
+        Json::object(vec![("start", self.position(span.start)), ("end", self.position(span.end))])
+    }
+
+    fn location(&self, span: Span) -> Json {
+        Json::object(vec![("uri", Json::string(self.uri)), ("range", self.range(span))])
+    }
+
+    // The token starting at `pos`, or the character there.
+    fn range_at(&self, pos: (usize, usize)) -> Json {
+        let tokens = self.tree.root.tokens();
+        match tokens.iter().find(|token| token.span.start == pos) {
+            Some(token) => self.range(token.span),
+            None => {
+                let end = self.line(pos.0).get(pos.1..).and_then(|rest| rest.chars().next()).map_or(0, char::len_utf8);
+                self.range(Span { start: pos, end: (pos.0, pos.1 + end) })
+            }
+        }
+    }
+
+    // All of the text, for edits replacing it.
+    fn whole_range(&self) -> Json {
+        let lines = self.text.split('\n').count();
+        let end = (lines - 1, self.line(lines - 1).len());
+        self.range(Span { start: (0, 0), end })
+    }
+}
+
+impl<'a> Declaration<'a> {
+    fn is_const(&self) -> bool {
+        self.node.kind == NodeKind::ConstDeclaration
+    }
+
+    // The text after `=`, with a constant's folded into its value.
+    fn value(&self) -> Option<String> {
+        let tokens = self.node.tokens();
+        let equals = tokens.iter().position(|token| token.kind == TokenType::EqualSign)?;
+        let text: String = tokens[equals + 1..]
+            .iter()
+            .filter(|token| token.kind != TokenType::Semicolon)
+            .map(|token| token.text.as_str())
+            .collect::<Vec<_>>()
+            .join(if self.is_const() { "" } else { " " });
+        match text.parse::<i64>() {
+            Ok(value) if self.is_const() => Some(value.to_string()),
+            _ => Some(text),
+        }
+    }
+
+    fn signature(&self) -> String {
+        let keyword = if self.is_const() { "const" } else { "var" };
+        match self.value() {
+            Some(value) => format!("{} {} = {}", keyword, self.name.text, value),
+            None => format!("{} {}", keyword, self.name.text),
+        }
+    }
+}
+
+fn initialize_result() -> Json {
+    Json::object(vec![
+        (
+            "capabilities",
+            Json::object(vec![
+                ("textDocumentSync", Json::number(1)),
+                ("definitionProvider", Json::Bool(true)),
+                ("referencesProvider", Json::Bool(true)),
+                ("hoverProvider", Json::Bool(true)),
+                ("documentSymbolProvider", Json::Bool(true)),
+                ("documentFormattingProvider", Json::Bool(true)),
+            ]),
+        ),
+        ("serverInfo", Json::object(vec![("name", Json::string("plc0-lsp"))])),
+    ])
+}
+
+fn definition(document: &Document, params: &Json) -> Json {
+    document
+        .identifier_at(params)
+        .and_then(|token| document.declaration_of(&token.text))
+        .map_or(Json::Null, |declaration| document.location(declaration.name.span))
+}
+
+fn references(document: &Document, params: &Json) -> Json {
+    let token = match document.identifier_at(params) {
+        Some(token) => token,
+        None => return Json::Null,
+    };
+    let declaration = document.declaration_of(&token.text).map(|declaration| declaration.name.span);
+    let include_declaration = params.at(&["context", "includeDeclaration"]).and_then(Json::as_bool).unwrap_or(true);
+    let locations = document
+        .identifiers()
+        .into_iter()
+        .filter(|other| other.text == token.text)
+        .filter(|other| include_declaration || Some(other.span) != declaration)
+        .map(|other| document.location(other.span))
+        .collect();
+    Json::Array(locations)
+}
+
+fn hover(document: &Document, params: &Json) -> Json {
+    let token = match document.identifier_at(params) {
+        Some(token) => token,
+        None => return Json::Null,
+    };
+    match document.declaration_of(&token.text) {
+        Some(declaration) => Json::object(vec![
+            (
+                "contents",
+                Json::object(vec![
+                    ("kind", Json::string("markdown")),
+                    ("value", Json::String(format!("```plc0\n{}\n```", declaration.signature()))),
+                ]),
+            ),
+            ("range", document.range(token.span)),
+        ]),
+        None => Json::Null,
+    }
+}
+
+fn document_symbols(document: &Document, _: &Json) -> Json {
+    let symbols = document
+        .declarations()
+        .iter()
+        .map(|declaration| {
+            let kind = if declaration.is_const() { SYMBOL_CONSTANT } else { SYMBOL_VARIABLE };
+            let mut symbol = vec![
+                ("name", Json::String(declaration.name.text.clone())),
+                ("kind", Json::number(kind)),
+                ("range", document.range(declaration.node.span().unwrap())),
+                ("selectionRange", document.range(declaration.name.span)),
+            ];
+            if let Some(value) = declaration.value() {
+                symbol.push(("detail", Json::String(format!("= {}", value))));
+            }
+            Json::object(symbol)
+        })
+        .collect();
+    Json::Array(symbols)
+}
+
+// One edit replacing the whole text; none when it is formatted already, null
+// when it can't be tokenized.
+fn formatting(document: &Document, _: &Json) -> Json {
+    match format_source(document.text) {
+        Ok(formatted) if formatted == document.text => Json::Array(Vec::new()),
+        Ok(formatted) => Json::Array(vec![Json::object(vec![
+            ("range", document.whole_range()),
+            ("newText", Json::String(formatted)),
+        ])]),
+        Err(_) => Json::Null,
+    }
+}
+
+fn publish_diagnostics(uri: &str, diagnostics: Vec<Json>) -> String {
+    let params = Json::object(vec![("uri", Json::string(uri)), ("diagnostics", Json::Array(diagnostics))]);
+    Json::object(vec![
+        ("jsonrpc", Json::string("2.0")),
+        ("method", Json::string("textDocument/publishDiagnostics")),
+        ("params", params),
+    ])
+    .to_string()
+}
+
+fn error_response(id: &Json, code: i32, message: &str) -> String {
+    let error = Json::object(vec![("code", Json::Number(code as f64)), ("message", Json::string(message))]);
+    Json::object(vec![("jsonrpc", Json::string("2.0")), ("id", id.clone()), ("error", error)]).to_string()
+}
@@ -3,16 +3,35 @@ extern crate getopts;
 
 use args::{Args, ArgsError};
 
-const PROGRAM_DESC: &'static str = "A Rust version for miniplc0 complier!";
-const PROGRAM_NAME: &'static str = "rMINIPLC0c";
+const PROGRAM_DESC: &str = "A Rust version for miniplc0 complier!";
+const PROGRAM_NAME: &str = "rMINIPLC0c";
 
-use std::{env, fs::File, io::{BufWriter, Write}};
+use std::{env, fmt, fs::{self, File}, io::{self, BufRead, BufWriter, Write}, path::Path, process};
 
-use rminiplc0::{analyze,tokenize};
+use rminiplc0::{
+    assemble, compile, disassemble, emit_c, emit_js, emit_jvm, emit_llvm, emit_riscv, emit_wat, emit_x86_64, is_bytecode, optimize_bytecode, tokenize, Bytecode, ControlFlowGraph,
+    lower, Debugger, Limits, RegisterMachine, Repl, RiscvTarget, SsaProgram, TraceFormat, VirtualMachine, WarningCode,
+};
 
+#[allow(clippy::upper_case_acronyms)]
 enum Modules {
     TOKENIZE,
     ANALYZE,
+    ANNOTATED,
+    CFG,
+    SSA,
+    RUN,
+    DEBUG,
+    REPL,
+    BYTECODE,
+    C,
+    X86_64,
+    RISCV(RiscvTarget),
+    WAT,
+    LLVM,
+    JVM,
+    JS,
+    REGISTERS,
     NOTHING,
 }
 
@@ -20,22 +39,185 @@ struct Target {
     task: Modules,
     input: String,
     output: String,
+    optimize: bool,
+    assembly: bool,
+    warnings: Vec<WarningCode>,
+    warnings_as_errors: bool,
+    trace: Option<String>,
+    trace_format: TraceFormat,
+    limits: Limits,
+    register_vm: bool,
 }
 
 fn main() {
     println!("Hello, world!");
     let target = parse(&env::args().collect()).expect("Please check your command line.");
-    if matches!(target.task, Modules::NOTHING) {
-        return;
+    match target.task {
+        Modules::NOTHING => return,
+        // The interactive modes write to the terminal, not to the output file.
+        Modules::REPL => {
+            let mut repl = Repl::new();
+            return interact("plc0> ", |line, output| repl.eval(line, output));
+        }
+        Modules::DEBUG => return debug(load_optimized(&target)),
+        _ => (),
     }
-    let mut writer = BufWriter::new(File::create(target.output).expect("unable to open output file"));
+    let mut writer = BufWriter::new(File::create(&target.output).expect("unable to open output file"));
     match target.task {
-        Modules::ANALYZE => analyze(target.input).iter().for_each(|instr| {write!(writer, "{}\n", instr).unwrap();return;}),
-        Modules::TOKENIZE => tokenize(target.input).iter().for_each(|token| {write!(writer, "{}\n", token).unwrap();return;}),
-        Modules::NOTHING => return
+        Modules::TOKENIZE => tokenize(target.input).iter().for_each(|token| writeln!(writer, "{}", token).unwrap()),
+        Modules::NOTHING | Modules::REPL | Modules::DEBUG => (),
+        _ => {
+            let bytecode = load_optimized(&target);
+            let instructions = bytecode.instructions.clone();
+            let line_table = bytecode.line_table.clone();
+            match target.task {
+                Modules::CFG => {
+                    let cfg = ControlFlowGraph::new(&instructions);
+                    write!(writer, "{}", cfg.to_dot(&instructions)).unwrap();
+                }
+                Modules::SSA => {
+                    let ssa = SsaProgram::from_instructions(&instructions).expect("failed to build SSA form");
+                    write!(writer, "{}", ssa).unwrap();
+                }
+                Modules::BYTECODE => writer.write_all(&generate(bytecode.to_bytes(), &target.output)).unwrap(),
+                Modules::C => write!(writer, "{}", generate(emit_c(&bytecode), &target.output)).unwrap(),
+                Modules::X86_64 => write!(writer, "{}", generate(emit_x86_64(&bytecode), &target.output)).unwrap(),
+                Modules::RISCV(riscv) => write!(writer, "{}", generate(emit_riscv(&bytecode, riscv), &target.output)).unwrap(),
+                Modules::WAT => write!(writer, "{}", generate(emit_wat(&bytecode), &target.output)).unwrap(),
+                Modules::LLVM => write!(writer, "{}", generate(emit_llvm(&bytecode), &target.output)).unwrap(),
+                Modules::REGISTERS => {
+                    let program = generate(lower(&instructions), &target.output);
+                    program.instructions.iter().for_each(|instr| writeln!(writer, "{}", instr).unwrap())
+                }
+                Modules::JS => write!(writer, "{}", generate(emit_js(&bytecode), &target.output)).unwrap(),
+                // `java` looks the class up by its file name.
+                Modules::JVM => {
+                    let class_name = Path::new(&target.output).file_stem().unwrap().to_string_lossy();
+                    writer.write_all(&generate(emit_jvm(&bytecode, &class_name), &target.output)).unwrap()
+                }
+                Modules::ANNOTATED => {
+                    let source = fs::read_to_string(&target.input).ok();
+                    write!(writer, "{}", disassemble(&bytecode, source.as_deref())).unwrap();
+                }
+                Modules::RUN if target.register_vm => {
+                    let mut machine = RegisterMachine::new(generate(lower(&instructions), &target.output));
+                    if let Some(line_table) = line_table {
+                        machine.set_line_table(line_table);
+                    }
+                    if let Err(err) = machine.run(&mut writer) {
+                        writer.flush().unwrap();
+                        eprintln!("runtime error: {}", err);
+                        process::exit(1);
+                    }
+                }
+                Modules::RUN => {
+                    let mut vm = VirtualMachine::new(instructions);
+                    vm.set_limits(target.limits);
+                    if let Some(line_table) = line_table {
+                        vm.set_line_table(line_table);
+                    }
+                    let result = match &target.trace {
+                        Some(path) => {
+                            let file = File::create(path).expect("unable to open trace file");
+                            vm.run_with_trace(&mut writer, &mut BufWriter::new(file), target.trace_format)
+                        }
+                        None => vm.run(&mut writer),
+                    };
+                    if let Err(err) = result {
+                        writer.flush().unwrap();
+                        eprintln!("runtime error: {}", err);
+                        process::exit(1);
+                    }
+                }
+                _ => instructions.iter().for_each(|instr| writeln!(writer, "{}", instr).unwrap()),
+            }
+        }
+    }
+}
+
+// Reads debugger commands from stdin; the program's output is shown between
+// them instead of going to the output file.
+fn debug(bytecode: Bytecode) {
+    let mut debugger = Debugger::new(bytecode);
+    interact("(plc0db) ", |line, output| debugger.execute(line, output));
+}
+
+// Prompts for lines on stdin until EOF or until `execute` returns false.
+fn interact<F>(prompt: &str, mut execute: F)
+where
+    F: FnMut(&str, &mut dyn Write) -> bool,
+{
+    let mut stdout = io::stdout();
+    let mut lines = io::stdin().lock().lines();
+    loop {
+        write!(stdout, "{}", prompt).unwrap();
+        stdout.flush().unwrap();
+        let line = match lines.next() {
+            Some(line) => line.expect("unable to read a command"),
+            None => break,
+        };
+        if !execute(&line, &mut stdout) {
+            break;
+        }
+    }
+}
+
+// The output of a backend, or the error, without leaving an empty output file
+// behind.
+fn generate<T, E: fmt::Display>(result: Result<T, E>, output: &str) -> T {
+    result.unwrap_or_else(|err| {
+        eprintln!("error: {}", err);
+        let _ = fs::remove_file(output);
+        process::exit(1);
+    })
+}
+
+fn load_optimized(target: &Target) -> Bytecode {
+    let bytecode = load(target);
+    if target.optimize {
+        optimize_bytecode(&bytecode)
+    } else {
+        bytecode
     }
 }
 
+// Compiles the input, or loads it directly when it is a bytecode file or,
+// with --assembly, an instruction listing.
+fn load(target: &Target) -> Bytecode {
+    let bytes = fs::read(&target.input).expect("cannot find input file");
+    if is_bytecode(&bytes) {
+        return Bytecode::from_bytes(&bytes).unwrap_or_else(|err| {
+            eprintln!("error: {}", err);
+            process::exit(1);
+        });
+    }
+    if target.assembly {
+        let source = String::from_utf8_lossy(&bytes);
+        let instructions = assemble(&source).unwrap_or_else(|err| {
+            eprintln!("error: {}", err);
+            process::exit(1);
+        });
+        return Bytecode::from_instructions(instructions);
+    }
+    let (bytecode, warnings) = compile(target.input.clone()).unwrap_or_else(|err| {
+        let (line, col) = err.get_pos();
+        eprintln!("error: {}:{}: {}", line + 1, col + 1, err.get_err_code());
+        process::exit(1);
+    });
+    let warnings: Vec<_> = warnings
+        .into_iter()
+        .filter(|warning| target.warnings.contains(warning.get_warn_code()))
+        .collect();
+    for warning in &warnings {
+        eprintln!("warning: {}", warning);
+    }
+    if target.warnings_as_errors && !warnings.is_empty() {
+        eprintln!("error: warnings are treated as errors");
+        process::exit(1);
+    }
+    bytecode
+}
+
 fn parse(input: &Vec<String>) -> Result<Target, ArgsError> {
     let mut args = Args::new(PROGRAM_NAME, PROGRAM_DESC);
     args.flag("h", "help", "Print this");
@@ -55,41 +237,183 @@ fn parse(input: &Vec<String>) -> Result<Target, ArgsError> {
         getopts::Occur::Req,
         Some(String::from("a.out")),
     );
+    args.option(
+        "e",
+        "emit",
+        "what to write: tokens, asm, asm-annotated, cfg, ssa, bytecode, c, x86-64, rv32, rv64, wat, llvm, jvm, js or registers",
+        "KIND",
+        getopts::Occur::Optional,
+        None,
+    );
     args.flag("t", "tokenize", "perform tokenization");
     args.flag("l", "analyze", "perform analyzation");
+    args.flag("g", "cfg", "write the control-flow graph in Graphviz DOT format");
+    args.flag("s", "ssa", "write the SSA intermediate representation");
+    args.flag("b", "bytecode", "write the program in the binary bytecode format");
+    args.flag("r", "run", "run the program or a bytecode file, its output goes to the output file");
+    args.option(
+        "T",
+        "trace",
+        "with --run, write every executed instruction and the stack after it to FILE",
+        "FILE",
+        getopts::Occur::Optional,
+        None,
+    );
+    args.option(
+        "",
+        "trace-format",
+        "text or json, the latter writes JSON Lines (default text)",
+        "FORMAT",
+        getopts::Occur::Optional,
+        None,
+    );
+    args.option(
+        "",
+        "max-steps",
+        "with --run, stop with an error after executing N instructions",
+        "N",
+        getopts::Occur::Optional,
+        None,
+    );
+    args.option(
+        "",
+        "max-stack",
+        "with --run, stop with an error when the stack would grow beyond N entries",
+        "N",
+        getopts::Occur::Optional,
+        None,
+    );
+    args.option(
+        "",
+        "max-output",
+        "with --run, stop with an error when the output would exceed N bytes",
+        "N",
+        getopts::Occur::Optional,
+        None,
+    );
+    args.flag(
+        "",
+        "register-vm",
+        "with --run, lower the program to registers and run it on the register machine",
+    );
+    args.flag("d", "debug", "step through the program, reading debugger commands from stdin");
+    args.flag("R", "repl", "read declarations and statements from stdin and run them as they are typed");
+    args.flag("a", "assembly", "the input is an instruction listing instead of a program");
+    args.flag("O", "optimize", "remove dead stores, unused variables and common subexpressions");
+    args.option(
+        "W",
+        "warn",
+        "all, none, error, a warning name, or no-<warning name> (default all)",
+        "WARNING",
+        getopts::Occur::Multi,
+        None,
+    );
     args.parse(input)?;
     let help = args.value_of("help")?;
     let input: String = args.value_of("input")?;
     let output: String = args.value_of("output")?;
-    if help {
-        print!("{}", args.full_usage());
-        return Ok(Target {
-            task: Modules::NOTHING,
-            input: String::new(),
-            output: String::new(),
-        });
+    let optimize: bool = args.value_of("optimize")?;
+    let assembly: bool = args.value_of("assembly")?;
+    let mut warnings = WarningCode::ALL.to_vec();
+    let mut warnings_as_errors = false;
+    if args.has_value("warn") {
+        for name in args.values_of::<String>("warn")? {
+            match name.as_str() {
+                "all" => warnings = WarningCode::ALL.to_vec(),
+                "none" => warnings.clear(),
+                "error" => warnings_as_errors = true,
+                _ => {
+                    let (enable, name) = match name.strip_prefix("no-") {
+                        Some(name) => (false, name),
+                        None => (true, name.as_str()),
+                    };
+                    let code = WarningCode::from_name(name)
+                        .ok_or_else(|| ArgsError::new("warn", &format!("unknown warning '{}'", name)))?;
+                    warnings.retain(|enabled| *enabled != code);
+                    if enable {
+                        warnings.push(code);
+                    }
+                }
+            }
+        }
     }
 
-    if args.value_of("tokenize")? {
-        return Ok(Target {
-            task : Modules::TOKENIZE,
-            input,
-            output
-        })
-    }
+    let trace: Option<String> = args.optional_value_of("trace")?;
+    let trace_format = match args.optional_value_of::<String>("trace-format")?.as_deref() {
+        None | Some("text") => TraceFormat::Text,
+        Some("json") => TraceFormat::JsonLines,
+        Some(format) => return Err(ArgsError::new("trace-format", &format!("unknown format '{}'", format))),
+    };
 
-    if args.value_of("analyze")? {
-        return Ok(Target {
-            task: Modules::ANALYZE,
-            input,
-            output
-        });
+    let limits = Limits {
+        max_instructions: args.optional_value_of("max-steps")?,
+        max_stack: args.optional_value_of("max-stack")?,
+        max_output: args.optional_value_of("max-output")?,
+    };
+
+    let register_vm: bool = args.value_of("register-vm")?;
+    if register_vm && (trace.is_some() || limits != Limits::default()) {
+        return Err(ArgsError::new("register-vm", "cannot be combined with --trace or the limits"));
     }
 
-    print!("{}", args.full_usage());
-    return Ok(Target {
-        task: Modules::NOTHING,
-        input: String::new(),
-        output: String::new(),
-    });
+    let emit: Option<String> = args.optional_value_of("emit")?;
+    let task = if help {
+        Modules::NOTHING
+    } else if let Some(kind) = emit {
+        match kind.as_str() {
+            "tokens" => Modules::TOKENIZE,
+            "asm" => Modules::ANALYZE,
+            "asm-annotated" => Modules::ANNOTATED,
+            "cfg" => Modules::CFG,
+            "ssa" => Modules::SSA,
+            "bytecode" => Modules::BYTECODE,
+            "c" => Modules::C,
+            "x86-64" => Modules::X86_64,
+            "rv32" => Modules::RISCV(RiscvTarget::Rv32),
+            "rv64" => Modules::RISCV(RiscvTarget::Rv64),
+            "wat" => Modules::WAT,
+            "llvm" => Modules::LLVM,
+            "jvm" => Modules::JVM,
+            "js" => Modules::JS,
+            "registers" => Modules::REGISTERS,
+            _ => return Err(ArgsError::new("emit", &format!("unknown kind '{}'", kind))),
+        }
+    } else if args.value_of("tokenize")? {
+        Modules::TOKENIZE
+    } else if args.value_of("cfg")? {
+        Modules::CFG
+    } else if args.value_of("ssa")? {
+        Modules::SSA
+    } else if args.value_of("bytecode")? {
+        Modules::BYTECODE
+    } else if args.value_of("run")? {
+        Modules::RUN
+    } else if args.value_of("debug")? {
+        Modules::DEBUG
+    } else if args.value_of("repl")? {
+        Modules::REPL
+    } else if args.value_of("analyze")? {
+        Modules::ANALYZE
+    } else {
+        Modules::NOTHING
+    };
+    if register_vm && !matches!(task, Modules::RUN) {
+        return Err(ArgsError::new("register-vm", "needs --run"));
+    }
+    if matches!(task, Modules::NOTHING) {
+        print!("{}", args.full_usage());
+    }
+    Ok(Target {
+        task,
+        input,
+        output,
+        optimize,
+        assembly,
+        warnings,
+        warnings_as_errors,
+        trace,
+        trace_format,
+        limits,
+        register_vm,
+    })
 }
@@ -0,0 +1,256 @@
+use crate::{
+    analyzer::{declarations, Instruction, Operation},
+    bytecode::Bytecode,
+    ir::{BinaryOp, SsaInst, SsaProgram, SsaValue},
+    line_table::LineTable,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    ops::Range,
+};
+
+// The instructions of one declaration or statement.
+type Unit = Range<usize>;
+
+// Optimized code, the index of the instruction each one comes from, and the
+// original index of each declared slot.
+type Optimized = (Vec<Instruction>, Vec<usize>, Vec<usize>);
+
+// Removes stores whose value is never loaded afterwards, and drops the slots
+// of variables that end up never used. Slot indices of LOD/STO are renumbered.
+// Stores of a division which may fail stay, the error being part of what the
+// program does. Code not laid out the way the analyzer emits it, e.g. from an
+// assembly listing, is returned unchanged.
+pub fn eliminate_dead_code(instructions: &[Instruction]) -> Vec<Instruction> {
+    eliminate_dead_code_with_origins(instructions).0
+}
+
+fn eliminate_dead_code_with_origins(instructions: &[Instruction]) -> Optimized {
+    let (declarations, statements) = match split_units(instructions) {
+        Some(units) => units,
+        None => {
+            let slots = (0..declarations(instructions).len()).collect();
+            return (instructions.to_vec(), (0..instructions.len()).collect(), slots);
+        }
+    };
+
+    // Backward liveness over the statements.
+    let mut live = HashSet::new();
+    let mut kept_statements = vec![false; statements.len()];
+    for (i, range) in statements.iter().enumerate().rev() {
+        let last = &instructions[range.end - 1];
+        if last.0 == Operation::STO && !live.remove(&last.1) && !may_fail(&instructions[range.clone()]) {
+            continue;
+        }
+        live.extend(loaded_slots(&instructions[range.clone()]));
+        kept_statements[i] = true;
+    }
+
+    // A declaration initializes its slot, so it is a store as well.
+    let mut kept_initializers = vec![false; declarations.len()];
+    for (slot, range) in declarations.iter().enumerate().rev() {
+        if live.remove(&(slot as i32)) || may_fail(&instructions[range.clone()]) {
+            live.extend(loaded_slots(&instructions[range.clone()]));
+            kept_initializers[slot] = true;
+        }
+    }
+
+    let mut used = HashSet::new();
+    let kept_code = declarations
+        .iter()
+        .zip(&kept_initializers)
+        .chain(statements.iter().zip(&kept_statements))
+        .filter(|(_, kept)| **kept)
+        .flat_map(|(range, _)| &instructions[range.clone()]);
+    for instr in kept_code {
+        if matches!(instr.0, Operation::LOD | Operation::STO) {
+            used.insert(instr.1);
+        }
+    }
+
+    let mut renumber = HashMap::new();
+    let mut slots = Vec::new();
+    let mut result = Vec::new();
+    let mut origins = Vec::new();
+    for (slot, range) in declarations.iter().enumerate() {
+        if !used.contains(&(slot as i32)) && !kept_initializers[slot] {
+            continue;
+        }
+        renumber.insert(slot as i32, renumber.len() as i32);
+        slots.push(slot);
+        if kept_initializers[slot] {
+            result.extend(renumbered(&instructions[range.clone()], &renumber));
+            origins.extend(range.clone());
+        } else {
+            result.push(Instruction(Operation::LIT, 0));
+            origins.push(range.start);
+        }
+    }
+    for (range, _) in statements.iter().zip(&kept_statements).filter(|(_, kept)| **kept) {
+        result.extend(renumbered(&instructions[range.clone()], &renumber));
+        origins.extend(range.clone());
+    }
+    (result, origins, slots)
+}
+
+// All passes: dead code elimination on the stack code, then common
+// subexpression elimination through the SSA form.
+pub fn optimize(instructions: &[Instruction]) -> Vec<Instruction> {
+    optimize_with_origins(instructions).0
+}
+
+// The same on a whole program: instructions keep the position of the code
+// they come from, slots their names, and the temporaries added for shared
+// values are named like unnamed slots.
+pub fn optimize_bytecode(bytecode: &Bytecode) -> Bytecode {
+    let (instructions, origins, slots) = optimize_with_origins(&bytecode.instructions);
+    let mut optimized = Bytecode::from_instructions(instructions);
+    optimized.const_count = slots.iter().filter(|&&slot| slot < bytecode.const_count as usize).count() as u32;
+    optimized.slot_names = bytecode.slot_names.as_ref().map(|names| {
+        (0..optimized.slot_count as usize)
+            .map(|slot| match slots.get(slot).and_then(|&original| names.get(original)) {
+                Some(name) => name.clone(),
+                None => format!("s_{}", slot),
+            })
+            .collect()
+    });
+    optimized.line_table = bytecode.line_table.as_ref().and_then(|table| {
+        let mut line_table = LineTable::new();
+        for &origin in &origins {
+            line_table.push(*table.get(origin)?);
+        }
+        Some(line_table)
+    });
+    optimized
+}
+
+fn optimize_with_origins(instructions: &[Instruction]) -> Optimized {
+    let (instructions, origins, slots) = eliminate_dead_code_with_origins(instructions);
+    let lowered = SsaProgram::from_instructions(&instructions)
+        .and_then(|program| eliminate_common_subexpressions(&program).to_instructions_with_origins());
+    match lowered {
+        // Declared slots stay where the stack code had them.
+        Ok((lowered, lowered_origins)) => {
+            let lowered_origins = lowered_origins.iter().map(|&origin| origins[origin]).collect();
+            (lowered, lowered_origins, slots)
+        }
+        Err(_) => (instructions, origins, slots),
+    }
+}
+
+// Value numbering: an instruction computing the same operation on the same
+// values as one that dominates it is replaced by that earlier value. A variable
+// reassigned in between holds a different SSA value, so it never matches.
+pub fn eliminate_common_subexpressions(program: &SsaProgram) -> SsaProgram {
+    let dominators = program.dominators();
+    let mut result = program.clone();
+    let mut replaced: HashMap<SsaValue, SsaValue> = HashMap::new();
+    let mut available: HashMap<SsaInst, (SsaValue, usize)> = HashMap::new();
+    for (b, block) in program.blocks.iter().enumerate() {
+        let mut kept = Vec::new();
+        for value in &block.insts {
+            let resolve = |v: &SsaValue| *replaced.get(v).unwrap_or(v);
+            let inst = match &program.insts[value.0] {
+                SsaInst::Binary(op, lhs, rhs) => {
+                    let (mut lhs, mut rhs) = (resolve(lhs), resolve(rhs));
+                    if matches!(op, BinaryOp::Add | BinaryOp::Mul) && rhs < lhs {
+                        std::mem::swap(&mut lhs, &mut rhs);
+                    }
+                    SsaInst::Binary(*op, lhs, rhs)
+                }
+                SsaInst::Phi(operands) => SsaInst::Phi(operands.iter().map(|(p, v)| (*p, resolve(v))).collect()),
+                SsaInst::Print(operand) => SsaInst::Print(resolve(operand)),
+                SsaInst::Store(slot, operand) => SsaInst::Store(*slot, resolve(operand)),
+                inst => inst.clone(),
+            };
+            if matches!(inst, SsaInst::Const(_) | SsaInst::Binary(..)) {
+                match available.get(&inst) {
+                    Some((earlier, def_block)) if dominators[b].contains(def_block) => {
+                        replaced.insert(*value, *earlier);
+                        continue;
+                    }
+                    _ => {
+                        available.insert(inst.clone(), (*value, b));
+                    }
+                }
+            }
+            result.insts[value.0] = inst;
+            kept.push(*value);
+        }
+        result.blocks[b].insts = kept;
+    }
+    // Phis may refer to values of blocks visited later through back edges.
+    for value in result.blocks.iter().flat_map(|block| &block.insts) {
+        if let SsaInst::Phi(operands) = &mut result.insts[value.0] {
+            for (_, operand) in operands.iter_mut() {
+                *operand = *replaced.get(operand).unwrap_or(operand);
+            }
+        }
+    }
+    result
+}
+
+// Splits the code into the initializers of each slot (in slot order) and the
+// statements, each statement ending with the STO or WRT that consumes its value.
+// None unless the initializers come first and the statements follow one after
+// the other, only using the slots below them.
+fn split_units(instructions: &[Instruction]) -> Option<(Vec<Unit>, Vec<Unit>)> {
+    let mut starts: Vec<usize> = Vec::new();
+    let mut statements: Vec<Unit> = Vec::new();
+    for (i, instr) in instructions.iter().enumerate() {
+        let (pops, pushes) = instr.0.stack_effect();
+        if starts.len() < pops {
+            return None;
+        }
+        let start = starts.drain(starts.len() - pops..).next().unwrap_or(i);
+        if pushes == 0 {
+            if statements.last().is_some_and(|last| last.end != start) {
+                return None;
+            }
+            statements.push(start..i + 1);
+        } else {
+            starts.push(start);
+        }
+    }
+    let body_start = statements.first().map_or(instructions.len(), |range| range.start);
+    if starts.last().is_some_and(|&start| start >= body_start) {
+        return None;
+    }
+    let ends = starts.iter().skip(1).copied().chain(Some(body_start));
+    let declarations: Vec<Unit> = starts.iter().zip(ends).map(|(&start, end)| start..end).collect();
+    let in_range = |range: &Unit, slots: usize| {
+        instructions[range.clone()]
+            .iter()
+            .filter(|instr| matches!(instr.0, Operation::LOD | Operation::STO))
+            .all(|instr| instr.1 >= 0 && (instr.1 as usize) < slots)
+    };
+    let valid = declarations.iter().enumerate().all(|(slot, range)| in_range(range, slot))
+        && statements.iter().all(|range| in_range(range, declarations.len()));
+    valid.then_some((declarations, statements))
+}
+
+// Whether the code divides by anything but a non-zero constant.
+fn may_fail(instructions: &[Instruction]) -> bool {
+    instructions.iter().enumerate().any(|(i, instr)| {
+        instr.0 == Operation::DIV
+            && !(i > 0 && instructions[i - 1].0 == Operation::LIT && instructions[i - 1].1 != 0)
+    })
+}
+
+fn loaded_slots(instructions: &[Instruction]) -> Vec<i32> {
+    instructions
+        .iter()
+        .filter(|instr| instr.0 == Operation::LOD)
+        .map(|instr| instr.1)
+        .collect()
+}
+
+fn renumbered<'a>(
+    instructions: &'a [Instruction],
+    renumber: &'a HashMap<i32, i32>,
+) -> impl Iterator<Item = Instruction> + 'a {
+    instructions.iter().map(move |instr| match instr.0 {
+        Operation::LOD | Operation::STO => Instruction(instr.0, renumber[&instr.1]),
+        _ => instr.clone(),
+    })
+}
@@ -0,0 +1,183 @@
+use crate::{
+    analyzer::{Instruction, Operation},
+    backend::{check, variable_positions},
+    error::{CodegenError, RuntimeError, RuntimeErrorCode},
+    ir::BinaryOp,
+    line_table::LineTable,
+};
+use std::{fmt, io::Write};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operand {
+    Register(usize),
+    Immediate(i32),
+}
+
+// Three-address instructions over a file of registers. Arithmetic wraps and
+// division by zero fails like the stack machine's.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RegisterInstruction {
+    Move(usize, Operand),
+    Binary(BinaryOp, usize, Operand, Operand),
+    Print(Operand),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RegisterProgram {
+    pub instructions: Vec<RegisterInstruction>,
+    // The stack instruction each instruction was lowered from, for errors.
+    pub origins: Vec<usize>,
+    pub registers: usize,
+}
+
+// Lowers stack code to registers: stack position N is register rN. Literals
+// and LOD don't move anything but become operands of the instruction using
+// them, so `a = b * c` is one `mul` and one `mov` instead of five stack
+// operations. Positions LOD/STO refer to are written as soon as they are
+// pushed; an operand still reading a register that STO is about to overwrite
+// is copied to its own register first.
+pub fn lower(instructions: &[Instruction]) -> Result<RegisterProgram, CodegenError> {
+    let registers = check(instructions)?;
+    let variables = variable_positions(instructions);
+
+    let mut program = RegisterProgram { instructions: Vec::new(), origins: Vec::new(), registers };
+    let mut stack: Vec<Operand> = Vec::new();
+    for (index, Instruction(op, operand)) in instructions.iter().enumerate() {
+        let mut emit = |instr: RegisterInstruction| {
+            program.instructions.push(instr);
+            program.origins.push(index);
+        };
+        let value = match op {
+            Operation::LIT => Operand::Immediate(*operand),
+            Operation::LOD => Operand::Register(*operand as usize),
+            Operation::STO => {
+                let target = *operand as usize;
+                let value = stack.pop().unwrap();
+                for (position, pending) in stack.iter_mut().enumerate() {
+                    if *pending == Operand::Register(target) && position != target {
+                        emit(RegisterInstruction::Move(position, *pending));
+                        *pending = Operand::Register(position);
+                    }
+                }
+                if value != Operand::Register(target) {
+                    emit(RegisterInstruction::Move(target, value));
+                }
+                continue;
+            }
+            Operation::WRT => {
+                emit(RegisterInstruction::Print(stack.pop().unwrap()));
+                continue;
+            }
+            Operation::ADD | Operation::SUB | Operation::MUL | Operation::DIV => {
+                let rhs = stack.pop().unwrap();
+                let lhs = stack.pop().unwrap();
+                let op = BinaryOp::from_operation(*op).unwrap();
+                emit(RegisterInstruction::Binary(op, stack.len(), lhs, rhs));
+                Operand::Register(stack.len())
+            }
+            Operation::ILL => unreachable!("rejected by check"),
+        };
+        let position = stack.len();
+        if variables.contains(&position) && value != Operand::Register(position) {
+            emit(RegisterInstruction::Move(position, value));
+            stack.push(Operand::Register(position));
+        } else {
+            stack.push(value);
+        }
+    }
+    Ok(program)
+}
+
+// Runs lowered programs; registers start at 0.
+#[derive(Clone)]
+pub struct RegisterMachine {
+    program: RegisterProgram,
+    pc: usize,
+    registers: Vec<i32>,
+    line_table: Option<LineTable>,
+    executed: u64,
+}
+
+impl RegisterMachine {
+    pub fn new(program: RegisterProgram) -> RegisterMachine {
+        let registers = vec![0; program.registers];
+        RegisterMachine { program, pc: 0, registers, line_table: None, executed: 0 }
+    }
+
+    // The line table of the stack code, which errors are reported against.
+    pub fn set_line_table(&mut self, line_table: LineTable) {
+        self.line_table = Some(line_table);
+    }
+
+    pub fn run(&mut self, output: &mut dyn Write) -> Result<(), RuntimeError> {
+        while self.step(output)? {}
+        Ok(())
+    }
+
+    // Executes one instruction, returns false once the program has finished.
+    pub fn step(&mut self, output: &mut dyn Write) -> Result<bool, RuntimeError> {
+        let instr = match self.program.instructions.get(self.pc) {
+            Some(instr) => *instr,
+            None => return Ok(false),
+        };
+        match instr {
+            RegisterInstruction::Move(dst, src) => self.registers[dst] = self.read(src),
+            RegisterInstruction::Binary(op, dst, lhs, rhs) => {
+                let (lhs, rhs) = (self.read(lhs), self.read(rhs));
+                self.registers[dst] = match op {
+                    BinaryOp::Add => lhs.wrapping_add(rhs),
+                    BinaryOp::Sub => lhs.wrapping_sub(rhs),
+                    BinaryOp::Mul => lhs.wrapping_mul(rhs),
+                    BinaryOp::Div if rhs == 0 => return Err(self.error(RuntimeErrorCode::ErrDivisionByZero)),
+                    BinaryOp::Div => lhs.wrapping_div(rhs),
+                };
+            }
+            RegisterInstruction::Print(src) => {
+                writeln!(output, "{}", self.read(src)).map_err(|_| self.error(RuntimeErrorCode::ErrOutput))?;
+            }
+        }
+        self.pc += 1;
+        self.executed += 1;
+        Ok(true)
+    }
+
+    pub fn get_registers(&self) -> &Vec<i32> {
+        &self.registers
+    }
+
+    pub fn get_executed(&self) -> u64 {
+        self.executed
+    }
+
+    fn read(&self, operand: Operand) -> i32 {
+        match operand {
+            Operand::Register(register) => self.registers[register],
+            Operand::Immediate(value) => value,
+        }
+    }
+
+    fn error(&self, err: RuntimeErrorCode) -> RuntimeError {
+        let index = self.program.origins[self.pc];
+        let span = self.line_table.as_ref().and_then(|table| table.get(index)).copied();
+        RuntimeError::new(index, err).with_span(span)
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Operand::Register(register) => write!(f, "r{}", register),
+            Operand::Immediate(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+impl fmt::Display for RegisterInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RegisterInstruction::Move(dst, src) => write!(f, "mov r{}, {}", dst, src),
+            RegisterInstruction::Binary(op, dst, lhs, rhs) => write!(f, "{} r{}, {}, {}", op, dst, lhs, rhs),
+            RegisterInstruction::Print(src) => write!(f, "print {}", src),
+        }
+    }
+}
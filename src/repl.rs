@@ -0,0 +1,105 @@
+use crate::{
+    analyzer::Analyzer,
+    error::CompilationError,
+    tokenizer::tokenize_source,
+    vm::VirtualMachine,
+};
+use std::io::Write;
+
+const HELP: &str = "\
+const NAME = VALUE;   declare a constant
+var NAME [= EXPR];    declare a variable
+NAME = EXPR;          assign a variable
+print(EXPR);          print a value
+:tokens TEXT          print the tokens of TEXT without running it
+:code                 print the instructions generated so far
+:vars                 print the constants and variables with their values
+:quit                 leave the REPL
+";
+
+// Declarations and statements typed one input at a time. The analyzer keeps
+// its symbol table between inputs and the VM its stack, so every input only
+// runs the instructions it added. An input that fails to compile or run is
+// dropped as a whole.
+pub struct Repl {
+    analyzer: Analyzer,
+    vm: VirtualMachine,
+}
+
+impl Default for Repl {
+    fn default() -> Repl {
+        Repl::new()
+    }
+}
+
+impl Repl {
+    pub fn new() -> Repl {
+        Repl {
+            analyzer: Analyzer::new(Vec::new()),
+            vm: VirtualMachine::new(Vec::new()),
+        }
+    }
+
+    // Returns false once the user asked to quit.
+    pub fn eval(&mut self, input: &str, output: &mut dyn Write) -> bool {
+        let input = input.trim();
+        let (command, rest) = match input.find(char::is_whitespace) {
+            Some(end) => (&input[..end], input[end..].trim()),
+            None => (input, ""),
+        };
+        match command {
+            ":quit" | ":q" => return false,
+            ":help" | ":h" => write!(output, "{}", HELP).unwrap(),
+            ":tokens" => match tokenize_source(rest) {
+                Ok(tokens) => tokens
+                    .iter()
+                    .for_each(|token| writeln!(output, "{:?} {}", token.get_type(), token).unwrap()),
+                Err(err) => report(&err, output),
+            },
+            ":code" => {
+                for (index, instr) in self.analyzer.instructions().iter().enumerate() {
+                    writeln!(output, "{:>4}  {}", index, instr).unwrap();
+                }
+            }
+            ":vars" => {
+                let stack = self.vm.get_stack();
+                for (slot, name) in self.analyzer.slot_names().iter().enumerate() {
+                    let kind = if slot < self.analyzer.const_count() { "const" } else { "var" };
+                    writeln!(output, "{} {} = {}", kind, name, stack[slot]).unwrap();
+                }
+            }
+            _ if command.starts_with(':') => {
+                writeln!(output, "error: unknown command '{}', try ':help'", command).unwrap()
+            }
+            _ => self.run(input, output),
+        }
+        true
+    }
+
+    fn run(&mut self, input: &str, output: &mut dyn Write) {
+        let tokens = match tokenize_source(input) {
+            Ok(tokens) => tokens,
+            Err(err) => return report(&err, output),
+        };
+        let mut analyzer = self.analyzer.clone();
+        let start = match analyzer.analyze_input(tokens) {
+            Ok(start) => start,
+            Err(err) => return report(&err, output),
+        };
+        let mut vm = self.vm.clone();
+        vm.append(&analyzer.instructions()[start..]);
+        vm.set_line_table(analyzer.line_table().clone());
+        match vm.run(output) {
+            Ok(()) => {
+                self.analyzer = analyzer;
+                self.vm = vm;
+            }
+            Err(err) => writeln!(output, "runtime error: {}", err).unwrap(),
+        }
+    }
+}
+
+fn report(err: &CompilationError, output: &mut dyn Write) {
+    let (line, col) = err.get_pos();
+    writeln!(output, "error: {} at {}:{}", err.get_err_code(), line + 1, col + 1).unwrap();
+}
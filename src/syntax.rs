@@ -0,0 +1,381 @@
+use crate::{
+    line_table::Span,
+    tokenizer::{check_keyword, TokenType},
+};
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TriviaKind {
+    Whitespace,
+    // "\n" or "\r\n".
+    Newline,
+    LineComment,
+    // Runs to the end of the source when it isn't closed.
+    BlockComment,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trivia {
+    pub kind: TriviaKind,
+    pub text: String,
+}
+
+// A token with the trivia around it. A token owns the trivia after it up to
+// the end of its line as trailing trivia; everything else before it, line
+// breaks included, is its leading trivia. Text the tokenizer would reject is
+// kept as a `NullToken`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxToken {
+    pub kind: TokenType,
+    pub text: String,
+    pub span: Span,
+    pub leading: Vec<Trivia>,
+    pub trailing: Vec<Trivia>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NodeKind {
+    Program,
+    ConstDeclaration,
+    VarDeclaration,
+    Assignment,
+    PrintStatement,
+    EmptyStatement,
+    BinaryExpression,
+    UnaryExpression,
+    ParenthesizedExpression,
+    Name,
+    Literal,
+    // Tokens out of place; the rest of the tree stays intact around them.
+    Error,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SyntaxElement {
+    Node(SyntaxNode),
+    Token(SyntaxToken),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxNode {
+    pub kind: NodeKind,
+    pub children: Vec<SyntaxElement>,
+}
+
+// A concrete syntax tree holding every character of the source, so printing
+// it gives back the exact text it was parsed from, whether the program is
+// valid or not.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxTree {
+    pub root: SyntaxNode,
+    // Trivia after the last token.
+    pub end_trivia: Vec<Trivia>,
+}
+
+// Splits the source into tokens with their trivia, never failing.
+pub fn lex(source: &str) -> (Vec<SyntaxToken>, Vec<Trivia>) {
+    let mut lexer = Lexer { source, offset: 0, pos: (0, 0) };
+    let mut tokens: Vec<SyntaxToken> = Vec::new();
+    loop {
+        if let Some(previous) = tokens.last_mut() {
+            previous.trailing = lexer.trivia(true);
+        }
+        let leading = lexer.trivia(false);
+        match lexer.token() {
+            Some((kind, text, span)) => tokens.push(SyntaxToken { kind, text, span, leading, trailing: Vec::new() }),
+            None => return (tokens, leading),
+        }
+    }
+}
+
+// Builds the syntax tree of `<程序>`, wrapping whatever doesn't fit the
+// grammar in `Error` nodes instead of stopping.
+pub fn parse(source: &str) -> SyntaxTree {
+    let (tokens, end_trivia) = lex(source);
+    let mut parser = Parser { tokens: tokens.into_iter().rev().collect() };
+    SyntaxTree { root: parser.program(), end_trivia }
+}
+
+struct Lexer<'a> {
+    source: &'a str,
+    offset: usize,
+    pos: (usize, usize),
+}
+
+impl<'a> Lexer<'a> {
+    fn rest(&self) -> &'a str {
+        &self.source[self.offset..]
+    }
+
+    fn advance(&mut self, len: usize) -> String {
+        let text = &self.source[self.offset..self.offset + len];
+        self.offset += len;
+        match text.rfind('\n') {
+            Some(newline) => self.pos = (self.pos.0 + text.matches('\n').count(), len - newline - 1),
+            None => self.pos.1 += len,
+        }
+        String::from(text)
+    }
+
+    // Trailing trivia stops in front of a line break.
+    fn trivia(&mut self, trailing: bool) -> Vec<Trivia> {
+        let mut trivia = Vec::new();
+        loop {
+            let rest = self.rest();
+            let (kind, len) = if rest.starts_with('\n') || rest.starts_with("\r\n") {
+                if trailing {
+                    return trivia;
+                }
+                (TriviaKind::Newline, if rest.starts_with('\n') { 1 } else { 2 })
+            } else if rest.starts_with("//") {
+                (TriviaKind::LineComment, rest.find(['\r', '\n']).unwrap_or(rest.len()))
+            } else if let Some(comment) = rest.strip_prefix("/*") {
+                (TriviaKind::BlockComment, comment.find("*/").map_or(rest.len(), |end| end + 4))
+            } else {
+                let len = rest
+                    .char_indices()
+                    .find(|&(at, ch)| !ch.is_whitespace() || ch == '\n' || rest[at..].starts_with("\r\n"))
+                    .map_or(rest.len(), |(at, _)| at);
+                if len == 0 {
+                    return trivia;
+                }
+                (TriviaKind::Whitespace, len)
+            };
+            let text = self.advance(len);
+            trivia.push(Trivia { kind, text });
+        }
+    }
+
+    fn token(&mut self) -> Option<(TokenType, String, Span)> {
+        let rest = self.rest();
+        let first = rest.chars().next()?;
+        let (kind, len) = if first.is_ascii_digit() {
+            (TokenType::UnsignedInteger, rest.find(|ch: char| !ch.is_ascii_digit()).unwrap_or(rest.len()))
+        } else if first.is_ascii_alphabetic() {
+            let len = rest.find(|ch: char| !ch.is_ascii_alphanumeric()).unwrap_or(rest.len());
+            (check_keyword(&rest[..len]), len)
+        } else {
+            let kind = match first {
+                '=' => TokenType::EqualSign,
+                '-' => TokenType::MinusSign,
+                '+' => TokenType::PlusSign,
+                '*' => TokenType::MultiplicationSign,
+                '/' => TokenType::DivisionSign,
+                '(' => TokenType::LeftBracket,
+                ')' => TokenType::RightBracket,
+                ';' => TokenType::Semicolon,
+                _ => TokenType::NullToken,
+            };
+            (kind, first.len_utf8())
+        };
+        let start = self.pos;
+        let text = self.advance(len);
+        Some((kind, text, Span { start, end: self.pos }))
+    }
+}
+
+struct Parser {
+    // Reversed, so the next token is popped off the end.
+    tokens: Vec<SyntaxToken>,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&TokenType> {
+        self.tokens.last().map(|token| &token.kind)
+    }
+
+    fn at(&self, kind: TokenType) -> bool {
+        self.peek() == Some(&kind)
+    }
+
+    // Moves the next token into `children` if it has the kind expected.
+    fn expect(&mut self, kind: TokenType, children: &mut Vec<SyntaxElement>) -> bool {
+        if !self.at(kind) {
+            return false;
+        }
+        children.push(SyntaxElement::Token(self.tokens.pop().unwrap()));
+        true
+    }
+
+    fn error(&mut self) -> SyntaxElement {
+        let token = SyntaxElement::Token(self.tokens.pop().unwrap());
+        SyntaxElement::Node(SyntaxNode { kind: NodeKind::Error, children: vec![token] })
+    }
+
+    // <程序> ::= 'begin'<主过程>'end'
+    fn program(&mut self) -> SyntaxNode {
+        let mut children = Vec::new();
+        while self.peek().is_some_and(|kind| *kind != TokenType::Begin) {
+            children.push(self.error());
+        }
+        self.expect(TokenType::Begin, &mut children);
+        while let Some(kind) = self.peek() {
+            let statement = match kind {
+                TokenType::End => break,
+                TokenType::Const => self.declaration(NodeKind::ConstDeclaration),
+                TokenType::Var => self.declaration(NodeKind::VarDeclaration),
+                TokenType::Identifier => self.assignment(),
+                TokenType::Print => self.print(),
+                TokenType::Semicolon => self.node(NodeKind::EmptyStatement, |parser, children| {
+                    parser.expect(TokenType::Semicolon, children);
+                }),
+                _ => self.error(),
+            };
+            children.push(statement);
+        }
+        self.expect(TokenType::End, &mut children);
+        while !self.tokens.is_empty() {
+            children.push(self.error());
+        }
+        SyntaxNode { kind: NodeKind::Program, children }
+    }
+
+    fn node(&mut self, kind: NodeKind, parse: impl FnOnce(&mut Parser, &mut Vec<SyntaxElement>)) -> SyntaxElement {
+        let mut children = Vec::new();
+        parse(self, &mut children);
+        SyntaxElement::Node(SyntaxNode { kind, children })
+    }
+
+    // 'const'<标识符>'='<常表达式>';' and 'var'<标识符>['='<表达式>]';'
+    fn declaration(&mut self, kind: NodeKind) -> SyntaxElement {
+        self.node(kind, |parser, children| {
+            children.push(SyntaxElement::Token(parser.tokens.pop().unwrap()));
+            parser.expect(TokenType::Identifier, children);
+            if parser.expect(TokenType::EqualSign, children) {
+                parser.expression(children);
+            }
+            parser.expect(TokenType::Semicolon, children);
+        })
+    }
+
+    // <标识符>'='<表达式>';'
+    fn assignment(&mut self) -> SyntaxElement {
+        self.node(NodeKind::Assignment, |parser, children| {
+            parser.expect(TokenType::Identifier, children);
+            if parser.expect(TokenType::EqualSign, children) {
+                parser.expression(children);
+            }
+            parser.expect(TokenType::Semicolon, children);
+        })
+    }
+
+    // 'print''('<表达式>')'';'
+    fn print(&mut self) -> SyntaxElement {
+        self.node(NodeKind::PrintStatement, |parser, children| {
+            parser.expect(TokenType::Print, children);
+            if parser.expect(TokenType::LeftBracket, children) {
+                parser.expression(children);
+                parser.expect(TokenType::RightBracket, children);
+            }
+            parser.expect(TokenType::Semicolon, children);
+        })
+    }
+
+    // <表达式> ::= <项>{<加法型运算符><项>}
+    fn expression(&mut self, children: &mut Vec<SyntaxElement>) {
+        self.binary(children, &[TokenType::PlusSign, TokenType::MinusSign], Parser::item);
+    }
+
+    // <项> ::= <因子>{<乘法型运算符><因子>}
+    fn item(&mut self, children: &mut Vec<SyntaxElement>) {
+        self.binary(children, &[TokenType::MultiplicationSign, TokenType::DivisionSign], Parser::factor);
+    }
+
+    // Folds `operand {operator operand}` to the left into BinaryExpressions.
+    fn binary(
+        &mut self,
+        children: &mut Vec<SyntaxElement>,
+        operators: &[TokenType],
+        operand: fn(&mut Parser, &mut Vec<SyntaxElement>),
+    ) {
+        let mut lhs = Vec::new();
+        operand(self, &mut lhs);
+        while self.peek().is_some_and(|kind| operators.contains(kind)) {
+            let mut binary = lhs;
+            binary.push(SyntaxElement::Token(self.tokens.pop().unwrap()));
+            operand(self, &mut binary);
+            lhs = vec![SyntaxElement::Node(SyntaxNode { kind: NodeKind::BinaryExpression, children: binary })];
+        }
+        children.extend(lhs);
+    }
+
+    // <因子> ::= [<符号>]( <标识符> | <无符号整数> | '('<表达式>')' )
+    fn factor(&mut self, children: &mut Vec<SyntaxElement>) {
+        let factor = match self.peek() {
+            Some(TokenType::PlusSign) | Some(TokenType::MinusSign) => {
+                self.node(NodeKind::UnaryExpression, |parser, children| {
+                    children.push(SyntaxElement::Token(parser.tokens.pop().unwrap()));
+                    parser.factor(children);
+                })
+            }
+            Some(TokenType::Identifier) => self.node(NodeKind::Name, |parser, children| {
+                parser.expect(TokenType::Identifier, children);
+            }),
+            Some(TokenType::UnsignedInteger) => self.node(NodeKind::Literal, |parser, children| {
+                parser.expect(TokenType::UnsignedInteger, children);
+            }),
+            Some(TokenType::LeftBracket) => self.node(NodeKind::ParenthesizedExpression, |parser, children| {
+                parser.expect(TokenType::LeftBracket, children);
+                parser.expression(children);
+                parser.expect(TokenType::RightBracket, children);
+            }),
+            _ => return,
+        };
+        children.push(factor);
+    }
+}
+
+impl SyntaxNode {
+    // The tokens of the node in source order.
+    pub fn tokens(&self) -> Vec<&SyntaxToken> {
+        let mut tokens = Vec::new();
+        for child in &self.children {
+            match child {
+                SyntaxElement::Node(node) => tokens.extend(node.tokens()),
+                SyntaxElement::Token(token) => tokens.push(token),
+            }
+        }
+        tokens
+    }
+
+    // From the start of the first token to the end of the last, trivia left
+    // out; None for nodes without tokens.
+    pub fn span(&self) -> Option<Span> {
+        let tokens = self.tokens();
+        Some(Span { start: tokens.first()?.span.start, end: tokens.last()?.span.end })
+    }
+
+    pub fn nodes(&self) -> impl Iterator<Item = &SyntaxNode> {
+        self.children.iter().filter_map(|child| match child {
+            SyntaxElement::Node(node) => Some(node),
+            SyntaxElement::Token(_) => None,
+        })
+    }
+}
+
+impl fmt::Display for Trivia {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+impl fmt::Display for SyntaxToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.leading.iter().try_for_each(|trivia| trivia.fmt(f))?;
+        f.write_str(&self.text)?;
+        self.trailing.iter().try_for_each(|trivia| trivia.fmt(f))
+    }
+}
+
+impl fmt::Display for SyntaxNode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.tokens().iter().try_for_each(|token| token.fmt(f))
+    }
+}
+
+impl fmt::Display for SyntaxTree {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.root.fmt(f)?;
+        self.end_trivia.iter().try_for_each(|trivia| trivia.fmt(f))
+    }
+}
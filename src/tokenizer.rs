@@ -1,15 +1,25 @@
 use std::fmt;
 use std::fs::File;
 use std::io::{self, BufRead};
-use std::ops::Deref;
 
-use crate::error::{CompilationError, ErrorCode};
+use crate::{
+    error::{CompilationError, ErrorCode},
+    line_table::Span,
+};
 
 pub struct Tokenizer<'a> {
     filename: &'a str,
     initialized: bool,
     lines_buffer: Vec<String>,
     ptr: (usize, usize),
+    comments: Vec<Comment>,
+}
+
+// A `// ...` or `/* ... */` comment, which the analyzer never sees.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comment {
+    pub text: String,
+    pub span: Span,
 }
 
 pub fn tokenize(input: String) -> Vec<Token> {
@@ -17,15 +27,45 @@ pub fn tokenize(input: String) -> Vec<Token> {
     tkz.get_all_tokens().unwrap()
 }
 
+// Tokenizes text instead of a file, e.g. a line typed into the REPL.
+pub fn tokenize_source(source: &str) -> Result<Vec<Token>, CompilationError> {
+    if source.trim().is_empty() {
+        return Ok(Vec::new());
+    }
+    Tokenizer::from_source(source).get_all_tokens()
+}
+
+// Like `tokenize_source`, also returning the comments, for tools that rewrite
+// the source.
+pub fn tokenize_with_comments(source: &str) -> Result<(Vec<Token>, Vec<Comment>), CompilationError> {
+    if source.trim().is_empty() {
+        return Ok((Vec::new(), Vec::new()));
+    }
+    let mut tkz = Tokenizer::from_source(source);
+    let tokens = tkz.get_all_tokens()?;
+    Ok((tokens, tkz.comments))
+}
+
 impl<'a> Tokenizer<'a> {
     pub fn new(input: &'a str) -> Tokenizer<'a> {
         Tokenizer {
             filename: input,
             initialized: false,
             lines_buffer: Vec::new(),
-            ptr: (0, 0)
+            ptr: (0, 0),
+            comments: Vec::new(),
+        }
+    }
+    pub fn from_source(source: &str) -> Tokenizer<'a> {
+        Tokenizer {
+            filename: "",
+            initialized: true,
+            lines_buffer: source.lines().map(line_with_break).collect(),
+            ptr: (0, 0),
+            comments: Vec::new(),
         }
     }
+
     pub fn get_next_token(&mut self) -> Result<Token, CompilationError> {
         if !self.initialized {
             self.read_all();
@@ -63,7 +103,7 @@ impl<'a> Tokenizer<'a> {
                         current = DFAState::InitialState;
                     } else if !ch.is_ascii_graphic() {
                         invalid = true;
-                    } else if ch.is_digit(10) {
+                    } else if ch.is_ascii_digit() {
                         current = DFAState::UnsignedIntegerState;
                     } else if ch.is_ascii_alphabetic() {
                         current = DFAState::IdentifierState;
@@ -84,7 +124,7 @@ impl<'a> Tokenizer<'a> {
                             }
                         }
                     }
-                    if !matches!(current, DFAState::InitialState) {
+                    if invalid || !matches!(current, DFAState::InitialState) {
                         pos = self.previous_pos();
                     }
                     if invalid {
@@ -100,7 +140,7 @@ impl<'a> Tokenizer<'a> {
                 }
                 DFAState::UnsignedIntegerState => {
                     let current_char = self.next_char();
-                    if current_char.map_or(true, |ch| !ch.is_digit(10)) {
+                    if current_char.is_none_or(|ch| !ch.is_ascii_digit()) {
                         if current_char.is_some() {
                             self.unread_last();
                         }
@@ -121,7 +161,7 @@ impl<'a> Tokenizer<'a> {
                 }
                 DFAState::IdentifierState => {
                     let current_char = self.next_char();
-                    if current_char.map_or(true, |ch| !ch.is_ascii_alphanumeric()) {
+                    if current_char.is_none_or(|ch| !ch.is_ascii_alphanumeric()) {
                         if current_char.is_some() {
                             self.unread_last();
                         }
@@ -132,44 +172,80 @@ impl<'a> Tokenizer<'a> {
                 }
 
                 DFAState::PlusSignState => {
-                    return Ok(Token::from_sign(TokenType::PlusSign, pos, self.ptr)?)
+                    return Token::from_sign(TokenType::PlusSign, pos, self.ptr)
                 }
 
                 DFAState::MinusSignState => {
-                    return Ok(Token::from_sign(TokenType::MinusSign, pos, self.ptr)?)
+                    return Token::from_sign(TokenType::MinusSign, pos, self.ptr)
                 }
 
                 DFAState::MultiplicationSignState => {
-                    return Ok(Token::from_sign(
+                    return Token::from_sign(
                         TokenType::MultiplicationSign,
                         pos,
                         self.ptr,
-                    )?)
+                    )
                 }
 
                 DFAState::DivisionSignState => {
-                    return Ok(Token::from_sign(TokenType::DivisionSign, pos, self.ptr)?)
+                    match self.lines_buffer[pos.0].as_bytes().get(pos.1 + 1) {
+                        Some(b'/') | Some(b'*') => {
+                            self.skip_comment(pos)?;
+                            ss.clear();
+                            current = DFAState::InitialState;
+                        }
+                        _ => return Token::from_sign(TokenType::DivisionSign, pos, self.ptr),
+                    }
                 }
 
                 DFAState::EqualSignState => {
-                    return Ok(Token::from_sign(TokenType::EqualSign, pos, self.ptr)?)
+                    return Token::from_sign(TokenType::EqualSign, pos, self.ptr)
                 }
 
                 DFAState::LeftbracketState => {
-                    return Ok(Token::from_sign(TokenType::LeftBracket, pos, self.ptr)?)
+                    return Token::from_sign(TokenType::LeftBracket, pos, self.ptr)
                 }
 
                 DFAState::RightbracketState => {
-                    return Ok(Token::from_sign(TokenType::RightBracket, pos, self.ptr)?)
+                    return Token::from_sign(TokenType::RightBracket, pos, self.ptr)
                 }
 
                 DFAState::SemicolonState => {
-                    return Ok(Token::from_sign(TokenType::Semicolon, pos, self.ptr)?)
+                    return Token::from_sign(TokenType::Semicolon, pos, self.ptr)
                 }
             }
         }
     }
 
+    // Records the comment starting at `start` and moves past it.
+    fn skip_comment(&mut self, start: (usize, usize)) -> Result<(), CompilationError> {
+        let first = &self.lines_buffer[start.0];
+        let end = if first.as_bytes()[start.1 + 1] == b'/' {
+            (start.0, first.trim_end().len())
+        } else {
+            (start.0..self.lines_buffer.len())
+                .find_map(|line| {
+                    let from = if line == start.0 { start.1 + 2 } else { 0 };
+                    self.lines_buffer[line][from..].find("*/").map(|col| (line, from + col + 2))
+                })
+                .ok_or_else(|| CompilationError::new_packed(start, ErrorCode::ErrIncompleteComment))?
+        };
+        let text = if start.0 == end.0 {
+            String::from(&first[start.1..end.1])
+        } else {
+            let mut lines = vec![first[start.1..].trim_end()];
+            lines.extend(self.lines_buffer[start.0 + 1..end.0].iter().map(|line| line.trim_end()));
+            lines.push(&self.lines_buffer[end.0][..end.1]);
+            lines.join("\n")
+        };
+        self.comments.push(Comment { text, span: Span { start, end } });
+        self.ptr = end;
+        while !self.is_EOF() && self.ptr.1 >= self.lines_buffer[self.ptr.0].len() {
+            self.ptr = (self.ptr.0 + 1, 0);
+        }
+        Ok(())
+    }
+
     fn next_char(&mut self) -> Option<char> {
         if self.is_EOF() {
             return None;
@@ -198,7 +274,7 @@ impl<'a> Tokenizer<'a> {
             panic!("Advance after EOF!");
         }
         let mut next = (self.ptr.0, self.ptr.1 + 1);
-        while next.0 <= self.lines_buffer.len() - 1 && next.1 >= self.lines_buffer[next.0].as_bytes().len() {
+        while next.0 < self.lines_buffer.len() && next.1 >= self.lines_buffer[next.0].len() {
             next = (next.0 + 1, 0);
         }
         next
@@ -210,14 +286,14 @@ impl<'a> Tokenizer<'a> {
         }
         let file = File::open(self.filename).expect("cannot find input file");
         for rl in io::BufReader::new(file).lines() {
-            self.lines_buffer.push(rl.unwrap());
+            self.lines_buffer.push(line_with_break(&rl.unwrap()));
         }
         self.initialized = true;
     }
 
     #[allow(non_snake_case)]
     fn is_EOF(&self) -> bool {
-        return self.ptr.0 >= self.lines_buffer.len();
+        self.ptr.0 >= self.lines_buffer.len()
     }
 
     fn unread_last(&mut self) {
@@ -225,7 +301,14 @@ impl<'a> Tokenizer<'a> {
     }
 }
 
-fn check_keyword(identifier: &str) -> TokenType {
+// Lines are stored without their line break, so a space stands in for it:
+// it separates a token at the end of a line from one at the start of the next,
+// and the character read last, which is never returned, is always this one.
+fn line_with_break(line: &str) -> String {
+    format!("{} ", line)
+}
+
+pub(crate) fn check_keyword(identifier: &str) -> TokenType {
     match identifier {
         "begin" => TokenType::Begin,
         "end" => TokenType::End,
@@ -258,7 +341,7 @@ pub enum TokenType {
 
 impl TokenType {
     pub fn to_string(&self) -> Result<String, CompilationError> {
-        Ok(String::from(match self.deref() {
+        Ok(String::from(match self {
             TokenType::EqualSign => "=",
             TokenType::PlusSign => "+",
             TokenType::MinusSign => "-",
@@ -309,12 +392,23 @@ impl Token {
         }
     }
 
+    pub fn get_start_pos(&self) -> (usize, usize) {
+        match self {
+            Token::Str(.., start, _) => *start,
+            Token::Integer(.., start, _) => *start
+        }
+    }
+
     pub fn get_end_pos(&self) -> (usize, usize) {
         match self {
             Token::Str(.., end) => *end,
             Token::Integer(.., end) => *end
         }
     }
+
+    pub fn get_span(&self) -> Span {
+        Span { start: self.get_start_pos(), end: self.get_end_pos() }
+    }
 }
 
 impl fmt::Display for Token {
@@ -326,6 +420,7 @@ impl fmt::Display for Token {
     }
 }
 
+#[allow(clippy::enum_variant_names)]
 enum DFAState {
     InitialState,
     UnsignedIntegerState,
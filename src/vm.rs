@@ -0,0 +1,192 @@
+use crate::{
+    analyzer::{Instruction, Operation},
+    error::{RuntimeError, RuntimeErrorCode},
+    line_table::LineTable,
+};
+use std::{convert::TryFrom, io::Write};
+
+pub fn run(instructions: Vec<Instruction>, output: &mut dyn Write) -> Result<(), RuntimeError> {
+    VirtualMachine::new(instructions).run(output)
+}
+
+// Bounds for running untrusted programs; `None` means unlimited.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Limits {
+    pub max_instructions: Option<u64>,
+    pub max_stack: Option<usize>,
+    pub max_output: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TraceFormat {
+    // `pc  instruction  [stack]`, aligned for reading and diffing.
+    Text,
+    // One `{"pc":..,"op":..,"operand":..,"stack":[..]}` object per line; the
+    // operand is null for operations without one.
+    JsonLines,
+}
+
+// The stack machine the analyzer compiles for. Slots are the bottom entries of
+// the stack, pushed by the declarations; arithmetic wraps like int32_t would on
+// every common target.
+#[derive(Clone)]
+pub struct VirtualMachine {
+    instructions: Vec<Instruction>,
+    pc: usize,
+    stack: Vec<i32>,
+    line_table: Option<LineTable>,
+    limits: Limits,
+    executed: u64,
+    output_bytes: usize,
+}
+
+impl VirtualMachine {
+    pub fn new(instructions: Vec<Instruction>) -> VirtualMachine {
+        VirtualMachine {
+            instructions,
+            pc: 0,
+            stack: Vec::new(),
+            line_table: None,
+            limits: Limits::default(),
+            executed: 0,
+            output_bytes: 0,
+        }
+    }
+
+    // More code to run after what was loaded so far, keeping the stack.
+    pub fn append(&mut self, instructions: &[Instruction]) {
+        self.instructions.extend_from_slice(instructions);
+    }
+
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    // Runtime errors then carry the source position of the failing instruction.
+    pub fn set_line_table(&mut self, line_table: LineTable) {
+        self.line_table = Some(line_table);
+    }
+
+    pub fn run(&mut self, output: &mut dyn Write) -> Result<(), RuntimeError> {
+        while self.step(output)? {}
+        Ok(())
+    }
+
+    // Like `run`, writing a line to `trace` after every executed instruction
+    // with the stack as it is afterwards.
+    pub fn run_with_trace(
+        &mut self,
+        output: &mut dyn Write,
+        trace: &mut dyn Write,
+        format: TraceFormat,
+    ) -> Result<(), RuntimeError> {
+        loop {
+            let pc = self.pc;
+            if !self.step(output)? {
+                return Ok(());
+            }
+            let instr = &self.instructions[pc];
+            let stack = self.stack.iter().map(|value| value.to_string()).collect::<Vec<_>>().join(", ");
+            match format {
+                TraceFormat::Text => writeln!(trace, "{:>5}  {:<10} [{}]", pc, instr.to_string(), stack),
+                TraceFormat::JsonLines => {
+                    let operand = if instr.0.has_operand() { instr.1.to_string() } else { String::from("null") };
+                    let stack = stack.replace(' ', "");
+                    writeln!(trace, r#"{{"pc":{},"op":"{:?}","operand":{},"stack":[{}]}}"#, pc, instr.0, operand, stack)
+                }
+            }
+            .map_err(|_| RuntimeError::new(pc, RuntimeErrorCode::ErrOutput))?;
+        }
+    }
+
+    // Executes one instruction, returns false once the program has finished.
+    pub fn step(&mut self, output: &mut dyn Write) -> Result<bool, RuntimeError> {
+        let Instruction(op, operand) = match self.instructions.get(self.pc) {
+            Some(instr) => instr.clone(),
+            None => return Ok(false),
+        };
+        if self.limits.max_instructions.is_some_and(|max| self.executed >= max) {
+            return Err(self.error(RuntimeErrorCode::ErrInstructionLimit));
+        }
+        match op {
+            Operation::ILL => return Err(self.error(RuntimeErrorCode::ErrIllegalInstruction)),
+            Operation::LIT => self.push(operand)?,
+            Operation::LOD => {
+                let value = *self.slot(operand)?;
+                self.push(value)?;
+            }
+            Operation::STO => {
+                let value = self.pop()?;
+                *self.slot(operand)? = value;
+            }
+            Operation::ADD | Operation::SUB | Operation::MUL | Operation::DIV => {
+                let rhs = self.pop()?;
+                let lhs = self.pop()?;
+                let value = match op {
+                    Operation::ADD => lhs.wrapping_add(rhs),
+                    Operation::SUB => lhs.wrapping_sub(rhs),
+                    Operation::MUL => lhs.wrapping_mul(rhs),
+                    _ if rhs == 0 => return Err(self.error(RuntimeErrorCode::ErrDivisionByZero)),
+                    _ => lhs.wrapping_div(rhs),
+                };
+                self.push(value)?;
+            }
+            Operation::WRT => {
+                let line = format!("{}\n", self.pop()?);
+                if self.limits.max_output.is_some_and(|max| self.output_bytes + line.len() > max) {
+                    return Err(self.error(RuntimeErrorCode::ErrOutputLimit));
+                }
+                output
+                    .write_all(line.as_bytes())
+                    .map_err(|_| self.error(RuntimeErrorCode::ErrOutput))?;
+                self.output_bytes += line.len();
+            }
+        }
+        self.pc += 1;
+        self.executed += 1;
+        Ok(true)
+    }
+
+    pub fn get_pc(&self) -> usize {
+        self.pc
+    }
+
+    pub fn get_stack(&self) -> &Vec<i32> {
+        &self.stack
+    }
+
+    pub fn get_executed(&self) -> u64 {
+        self.executed
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.pc >= self.instructions.len()
+    }
+
+    fn push(&mut self, value: i32) -> Result<(), RuntimeError> {
+        if self.limits.max_stack.is_some_and(|max| self.stack.len() >= max) {
+            return Err(self.error(RuntimeErrorCode::ErrStackLimit));
+        }
+        self.stack.push(value);
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Result<i32, RuntimeError> {
+        match self.stack.pop() {
+            Some(value) => Ok(value),
+            None => Err(self.error(RuntimeErrorCode::ErrStackUnderflow)),
+        }
+    }
+
+    fn slot(&mut self, index: i32) -> Result<&mut i32, RuntimeError> {
+        match usize::try_from(index) {
+            Ok(index) if index < self.stack.len() => Ok(&mut self.stack[index]),
+            _ => Err(self.error(RuntimeErrorCode::ErrInvalidSlot)),
+        }
+    }
+
+    fn error(&self, err: RuntimeErrorCode) -> RuntimeError {
+        let span = self.line_table.as_ref().and_then(|table| table.get(self.pc)).copied();
+        RuntimeError::new(self.pc, err).with_span(span)
+    }
+}